@@ -0,0 +1,124 @@
+//! Keyboard layouts, which say which keys are typed with which hand.
+//!
+//! There's a built-in catalog, and more can be added with a config file like
+//! this, where each layout lists the keys for each hand:
+//!
+//! ```toml
+//! [[layout]]
+//! name = "bepo"
+//! left = "bepoauiyxk123456"
+//! right = "vdljzwctsrnmgqhf7890"
+//! ```
+//!
+//! Layouts in the config file replace built-in layouts with the same name.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use miette::{IntoDiagnostic, Result, WrapErr};
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct Layout {
+    pub name: String,
+
+    /// The keys typed with the left hand.
+    pub left: String,
+
+    /// The keys typed with the right hand.
+    pub right: String,
+}
+
+/// The built-in layouts, as `(name, left, right)`.
+const BUILT_IN: &[(&str, &str, &str)] = &[
+    ("off", "", ""),
+    ("qwerty", "qwertasdfgzxcvb123456", "yuiophjklnm7890"),
+    ("dvorak", "aoeuptqjkx123456", "bcdfghilmnrsvwyz7890"),
+    ("colemak", "qwfpgarstdzxcvb12345", "jluyhneiokm67890"),
+    ("azerty", "azertqsdfgwxcvb123456", "yuiophjklmn7890"),
+    ("qwertz", "qwertasdfgyxcvb123456", "zuiophjklnmüöä7890"),
+    ("workman", "qdrwbashtgzxmcv123456", "jfupyneoikl7890"),
+    ("neo", "xvlcwuiaeoüöäpz123456", "khgfqßsnrtdybmj7890"),
+];
+
+#[derive(Debug, serde::Deserialize)]
+struct Config {
+    #[serde(default, rename = "layout")]
+    layouts: Vec<Layout>,
+}
+
+/// All the known layouts, including any from a config file.
+///
+/// If `config` isn't given, `layouts.toml` in the user's config directory is
+/// used if it's there.
+pub fn catalog(config: Option<&Path>) -> Result<Vec<Layout>> {
+    let mut layouts = BUILT_IN
+        .iter()
+        .map(|(name, left, right)| Layout {
+            name: name.to_string(),
+            left: left.to_string(),
+            right: right.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    let config = match config {
+        Some(path) => Some(path.to_owned()),
+        None => default_config().filter(|path| path.exists()),
+    };
+
+    if let Some(path) = config {
+        for layout in read_config(&path)? {
+            layouts.retain(|l| l.name != layout.name);
+            layouts.push(layout);
+        }
+    }
+
+    Ok(layouts)
+}
+
+/// Find a layout by name, ignoring case.
+pub fn find<'a>(layouts: &'a [Layout], name: &str) -> Result<&'a Layout> {
+    layouts
+        .iter()
+        .find(|layout| layout.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names = layouts
+                .iter()
+                .map(|layout| layout.name.as_str())
+                .collect::<Vec<_>>();
+            miette::miette!(
+                "there is no keyboard layout named `{name}`, the known layouts are: {}",
+                names.join(", ")
+            )
+        })
+}
+
+fn read_config(path: &Path) -> Result<Vec<Layout>> {
+    let input = fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("cannot read layouts from `{}`", path.display()))?;
+
+    let config: Config = toml::from_str(&input)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("cannot read layouts from `{}`", path.display()))?;
+
+    Ok(config
+        .layouts
+        .into_iter()
+        .map(|layout| Layout {
+            left: layout.left.to_lowercase(),
+            right: layout.right.to_lowercase(),
+            ..layout
+        })
+        .collect())
+}
+
+fn default_config() -> Option<PathBuf> {
+    let directory = match env::var_os("XDG_CONFIG_HOME") {
+        Some(directory) => PathBuf::from(directory),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(directory.join("dexterous-datalog").join("layouts.toml"))
+}
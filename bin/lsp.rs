@@ -0,0 +1,446 @@
+//! A language server, so that editors can show diagnostics as you type, jump
+//! from a relation to the facts and rules which define it, and complete
+//! relation names.
+
+use std::{collections::HashMap, rc::Rc, time::Duration};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+        Notification as _, PublishDiagnostics,
+    },
+    request::{Completion, GotoDefinition, HoverRequest, Request as _},
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    DiagnosticRelatedInformation, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DidSaveTextDocumentParams,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, NumberOrString, OneOf, Position,
+    PublishDiagnosticsParams, Range, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, TextDocumentSyncSaveOptions, Url,
+};
+use miette::{Diagnostic, IntoDiagnostic, Result};
+
+use datalog::{DataSet, Diagnostics, IdentifierPolicy, Limits, Mention, MentionKind, Program};
+
+use crate::report;
+
+/// Answer requests on stdin and stdout until the editor says to stop.
+pub fn serve(policy: Rc<dyn IdentifierPolicy>) -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::FULL),
+                save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                ..TextDocumentSyncOptions::default()
+            },
+        )),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..ServerCapabilities::default()
+    };
+    let capabilities = serde_json::to_value(capabilities).into_diagnostic()?;
+    connection.initialize(capabilities).into_diagnostic()?;
+
+    let mut server = Server {
+        connection: &connection,
+        policy,
+        documents: HashMap::new(),
+    };
+    server.run()?;
+
+    // The writer thread stops once nothing can send to it any more.
+    drop(connection);
+    io_threads.join().into_diagnostic()
+}
+
+struct Server<'c> {
+    connection: &'c Connection,
+    policy: Rc<dyn IdentifierPolicy>,
+    documents: HashMap<Url, Document>,
+}
+
+impl Server<'_> {
+    fn run(&mut self) -> Result<()> {
+        for message in &self.connection.receiver {
+            match message {
+                Message::Request(request) => {
+                    if self
+                        .connection
+                        .handle_shutdown(&request)
+                        .into_diagnostic()?
+                    {
+                        return Ok(());
+                    }
+                    let response = self.request(request);
+                    self.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => self.notification(notification)?,
+                Message::Response(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send(&self, message: Message) -> Result<()> {
+        self.connection.sender.send(message).into_diagnostic()
+    }
+
+    fn request(&self, request: Request) -> Response {
+        let Request { id, method, params } = request;
+        match method.as_str() {
+            GotoDefinition::METHOD => respond(id, params, |params: GotoDefinitionParams| {
+                let at = params.text_document_position_params;
+                self.definition(&at.text_document.uri, at.position)
+            }),
+            HoverRequest::METHOD => respond(id, params, |params: HoverParams| {
+                let at = params.text_document_position_params;
+                self.hover(&at.text_document.uri, at.position)
+            }),
+            Completion::METHOD => respond(id, params, |params: CompletionParams| {
+                self.completion(&params.text_document_position.text_document.uri)
+            }),
+            _ => Response::new_err(
+                id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unknown method `{method}`"),
+            ),
+        }
+    }
+
+    fn notification(&mut self, notification: Notification) -> Result<()> {
+        let Notification { method, params } = notification;
+        match method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    serde_json::from_value(params).into_diagnostic()?;
+                let document = params.text_document;
+                self.update(document.uri, document.text, true)
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams =
+                    serde_json::from_value(params).into_diagnostic()?;
+                // With full syncing, the last change is the whole text.
+                match params.content_changes.into_iter().last() {
+                    Some(change) => self.update(params.text_document.uri, change.text, false),
+                    None => Ok(()),
+                }
+            }
+            DidSaveTextDocument::METHOD => {
+                let params: DidSaveTextDocumentParams =
+                    serde_json::from_value(params).into_diagnostic()?;
+                let uri = params.text_document.uri;
+                match self.documents.get(&uri) {
+                    Some(document) => {
+                        let text = document.text.clone();
+                        self.update(uri, text, true)
+                    }
+                    None => Ok(()),
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams =
+                    serde_json::from_value(params).into_diagnostic()?;
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                self.publish(uri, Vec::new())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn update(&mut self, uri: Url, text: String, run: bool) -> Result<()> {
+        let document = Document::new(text, self.policy.clone(), &uri, run);
+        let diagnostics = document.diagnostics.clone();
+        self.documents.insert(uri.clone(), document);
+        self.publish(uri, diagnostics)
+    }
+
+    fn publish(&self, uri: Url, diagnostics: Vec<lsp_types::Diagnostic>) -> Result<()> {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        self.send(Message::Notification(notification))
+    }
+
+    // From a relation to the facts, rules and `.input`s which give it facts.
+    fn definition(&self, uri: &Url, position: Position) -> Option<GotoDefinitionResponse> {
+        let document = self.documents.get(uri)?;
+        let relation = document.mention_at(position)?.relation.clone();
+
+        let locations = document
+            .mentions
+            .iter()
+            .filter(|mention| mention.relation == relation && mention.kind.defines())
+            .map(|mention| Location {
+                uri: uri.clone(),
+                range: document.range(mention.span.clone()),
+            })
+            .collect();
+
+        Some(GotoDefinitionResponse::Array(locations))
+    }
+
+    // Like "`parent`/2: 3 facts, 1 rule".
+    fn hover(&self, uri: &Url, position: Position) -> Option<Hover> {
+        let document = self.documents.get(uri)?;
+        let mention = document.mention_at(position)?;
+        let relation = mention.relation.as_str();
+
+        let facts = document
+            .data
+            .relations()
+            .find(|info| info.name == relation)
+            .map_or(0, |info| info.len);
+        let rules = document
+            .mentions
+            .iter()
+            .filter(|m| m.relation == relation && m.kind == MentionKind::Head)
+            .count();
+
+        let s = |n: usize| if n == 1 { "" } else { "s" };
+        let value = format!(
+            "`{}`: {facts} fact{}, {rules} rule{}",
+            document.signature(relation),
+            s(facts),
+            s(rules),
+        );
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(document.range(mention.span.clone())),
+        })
+    }
+
+    fn completion(&self, uri: &Url) -> Option<CompletionResponse> {
+        let document = self.documents.get(uri)?;
+
+        let mut names: Vec<&str> = document
+            .mentions
+            .iter()
+            .map(|mention| mention.relation.as_str())
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+
+        let items = names
+            .into_iter()
+            .map(|name| CompletionItem {
+                label: name.to_string(),
+                kind: Some(CompletionItemKind::CLASS),
+                detail: Some(document.signature(name)),
+                ..CompletionItem::default()
+            })
+            .collect();
+
+        Some(CompletionResponse::Array(items))
+    }
+}
+
+// Answer a request with whatever `f` makes of its parameters, or with an
+// error if they don't make sense.
+fn respond<P, R>(id: RequestId, params: serde_json::Value, f: impl FnOnce(P) -> R) -> Response
+where
+    P: serde::de::DeserializeOwned,
+    R: serde::Serialize,
+{
+    match serde_json::from_value(params) {
+        Ok(params) => Response::new_ok(id, f(params)),
+        Err(error) => Response::new_err(id, ErrorCode::InvalidParams as i32, error.to_string()),
+    }
+}
+
+/// How long a document's rules can run for, to count the facts for hovers.
+const RUN_TIME: Duration = Duration::from_secs(1);
+
+/// An open file, and everything we know about it.
+struct Document {
+    text: String,
+    mentions: Vec<Mention>,
+    diagnostics: Vec<lsp_types::Diagnostic>,
+
+    /// The file's facts and rules, run if the file has been saved since it
+    /// last changed, for counting facts.
+    data: DataSet,
+}
+
+impl Document {
+    /// Running the rules can take a while, so it's only done with `run`, when
+    /// a file is opened or saved, and not on every change.
+    fn new(text: String, policy: Rc<dyn IdentifierPolicy>, uri: &Url, run: bool) -> Self {
+        let mut diagnostics = Diagnostics::default();
+        let program = Program::parse_with_diagnostics(&text, policy, &mut diagnostics);
+
+        let mut data = DataSet::default();
+        let mut mentions = Vec::new();
+        if let Some(program) = &program {
+            if !diagnostics.has_errors() {
+                data.check(program, &mut diagnostics);
+            }
+            // Problems loading are for running the program to report, not
+            // for the editor, and `.input`s would read files relative to
+            // wherever the server happens to be. Rules which take too long
+            // only get the facts they started with, so a typo can't hang the
+            // editor.
+            if data.program(&program.clone().without_inputs()).is_ok() && run {
+                let limits = Limits {
+                    time: Some(RUN_TIME),
+                    ..Limits::default()
+                };
+                // Constraints can only be checked once every fact is known.
+                if data.run_with_limits(&limits).is_ok() {
+                    data.check_constraints(&mut diagnostics);
+                }
+            }
+            mentions = program.mentions();
+        }
+
+        let mut document = Document {
+            text,
+            mentions,
+            diagnostics: Vec::new(),
+            data,
+        };
+
+        let (errors, lints) = diagnostics.into_parts();
+        let mut converted = Vec::new();
+        for error in &errors {
+            document.convert(error, uri, &mut converted);
+        }
+        for lint in &lints {
+            document.convert(lint, uri, &mut converted);
+        }
+        document.diagnostics = converted;
+
+        document
+    }
+
+    // Turn a diagnostic into LSP ones: one for it, pointing at its first
+    // label, and one for each related diagnostic.
+    fn convert(
+        &self,
+        diagnostic: &dyn Diagnostic,
+        uri: &Url,
+        out: &mut Vec<lsp_types::Diagnostic>,
+    ) {
+        let mut labels = diagnostic.labels().into_iter().flatten();
+        let first = labels.next();
+
+        let mut message = report::message(diagnostic);
+        if let Some(label) = first.as_ref().and_then(|label| label.label()) {
+            message = format!("{message}: {label}");
+        }
+        if let Some(help) = diagnostic.help() {
+            message = format!("{message}\nhelp: {help}");
+        }
+
+        let severity = match report::severity(diagnostic) {
+            "warning" => DiagnosticSeverity::WARNING,
+            "note" => DiagnosticSeverity::INFORMATION,
+            _ => DiagnosticSeverity::ERROR,
+        };
+
+        let related: Vec<_> = labels
+            .map(|label| DiagnosticRelatedInformation {
+                location: Location {
+                    uri: uri.clone(),
+                    range: self.range(label.offset()..label.offset() + label.len()),
+                },
+                message: label.label().unwrap_or_default().to_string(),
+            })
+            .collect();
+
+        out.push(lsp_types::Diagnostic {
+            range: first.map_or_else(Range::default, |label| {
+                self.range(label.offset()..label.offset() + label.len())
+            }),
+            severity: Some(severity),
+            code: diagnostic
+                .code()
+                .map(|code| NumberOrString::String(code.to_string())),
+            source: Some("dexterous-datalog".to_string()),
+            message,
+            related_information: (!related.is_empty()).then_some(related),
+            ..lsp_types::Diagnostic::default()
+        });
+
+        for related in diagnostic.related().into_iter().flatten() {
+            self.convert(related, uri, out);
+        }
+    }
+
+    fn mention_at(&self, position: Position) -> Option<&Mention> {
+        let offset = self.offset(position);
+        self.mentions
+            .iter()
+            .find(|mention| mention.span.start <= offset && offset <= mention.span.end)
+    }
+
+    // Like `parent/2`, or just `parent` if its arity isn't known.
+    fn signature(&self, relation: &str) -> String {
+        let arity = self
+            .mentions
+            .iter()
+            .filter(|mention| mention.relation == relation)
+            .find_map(|mention| mention.arity);
+        match arity {
+            Some(arity) => format!("{relation}/{arity}"),
+            None => relation.to_string(),
+        }
+    }
+
+    // LSP positions count lines from 0, and characters in UTF-16 code units.
+    fn position(&self, offset: usize) -> Position {
+        let before = &self.text[..floor_char_boundary(&self.text, offset)];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Position {
+            line: before.matches('\n').count() as u32,
+            character: before[line_start..].encode_utf16().count() as u32,
+        }
+    }
+
+    fn range(&self, span: std::ops::Range<usize>) -> Range {
+        Range {
+            start: self.position(span.start),
+            end: self.position(span.end),
+        }
+    }
+
+    fn offset(&self, position: Position) -> usize {
+        let mut line_start = 0;
+        for _ in 0..position.line {
+            match self.text[line_start..].find('\n') {
+                Some(i) => line_start += i + 1,
+                None => return self.text.len(),
+            }
+        }
+
+        let mut units = 0;
+        for (i, c) in self.text[line_start..].char_indices() {
+            if units >= position.character as usize || c == '\n' {
+                return line_start + i;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+}
+
+fn floor_char_boundary(text: &str, offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
@@ -1,12 +1,31 @@
 //! The command line interface for the datalog engine.
 
+// `datalog::Error` is big, but there's at most one per line of input.
+#![allow(clippy::result_large_err)]
+
 use clap::{Parser, ValueEnum};
-use miette::{Diagnostic, GraphicalReportHandler, IntoDiagnostic, NamedSource, Report, Result};
+use miette::{IntoDiagnostic, Result};
 use rustyline::{error::ReadlineError, Editor};
 
-use std::{ffi::OsString, fs};
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::Duration,
+};
+
+use datalog::{
+    Alternating, BlockList, Cancel, DataSet, Diagnostics, Error, ErrorKind, IdentifierPolicy,
+    Limits, Program, Query, Repl, Trace,
+};
+
+mod layout;
+mod lsp;
+mod report;
 
-use datalog::{BlockList, DataSet, Error, Program, Query, Repl};
+use report::{MessageFormat, Reporter};
 
 #[derive(Debug, clap::Parser)]
 #[command(author, version, about, long_about = None)]
@@ -19,52 +38,230 @@ struct Args {
     #[arg(short, long)]
     query: Option<String>,
 
-    /// Filter out left-hand letters for a keyboard layout.
+    /// Load a CSV or TSV file as facts, like an `.input` directive. This can
+    /// be given more than once.
+    #[arg(short, long, value_name = "RELATION=FILE", value_parser = relation_file)]
+    input: Vec<(String, OsString)>,
+
+    /// Write a relation to a CSV, TSV or JSON file after running, like an
+    /// `.output` directive. This can be given more than once.
+    #[arg(short, long, value_name = "RELATION=FILE", value_parser = relation_file)]
+    output: Vec<(String, OsString)>,
+
+    /// The keyboard layout used to filter identifiers, or `off`.
+    #[arg(long, value_name = "LAYOUT", default_value = "qwerty")]
+    filter: String,
+
+    /// Which hand identifiers can be typed with.
     #[arg(long, value_enum, default_value_t)]
-    filter: Filter,
+    hand: Hand,
+
+    /// A TOML file with more keyboard layouts. By default this is
+    /// `dexterous-datalog/layouts.toml` in your config directory.
+    #[arg(long, value_name = "FILE")]
+    layouts: Option<PathBuf>,
+
+    /// List the known keyboard layouts and exit.
+    #[arg(long)]
+    list_layouts: bool,
 
     /// Launch the interactive repl after loading a file. This is the default
     /// behaviour when no FILENAME is given.
     #[arg(long, short, conflicts_with = "query")]
     repl: bool,
+
+    /// A directory to keep the data set in. Facts and rules which are added
+    /// are saved there, and loaded again next time.
+    #[arg(long, value_name = "DIR")]
+    db: Option<OsString>,
+
+    /// Treat warnings as errors. Notes are still fine.
+    #[arg(long)]
+    deny_warnings: bool,
+
+    /// How many threads to apply rules on. By default there's one for each
+    /// core. The results are the same either way.
+    #[arg(long, value_name = "N")]
+    threads: Option<usize>,
+
+    /// Answer queries top-down, only looking at the rules and facts they
+    /// need, instead of running every rule first.
+    #[arg(long)]
+    top_down: bool,
+
+    /// Give up on running the rules after this many seconds.
+    #[arg(long, value_name = "SECONDS", value_parser = seconds)]
+    timeout: Option<Duration>,
+
+    /// Give up on running the rules once they derive more than this many
+    /// new facts.
+    #[arg(long, value_name = "N")]
+    max_facts: Option<usize>,
+
+    /// Give up on running the rules if they haven't found every fact after
+    /// applying each of them this many times.
+    #[arg(long, value_name = "N")]
+    max_iterations: Option<usize>,
+
+    /// Print how long each rule took, and what it found, after running.
+    #[arg(long)]
+    profile: bool,
+
+    /// Print each step of running the rules, and every fact they derive with
+    /// the rule and binding it came from, to standard error.
+    #[arg(long)]
+    trace: bool,
+
+    /// Only trace the facts in this relation. This can be given more than
+    /// once.
+    #[arg(long, value_name = "RELATION", requires = "trace")]
+    trace_relation: Vec<String>,
+
+    /// Write the trace to a file instead of standard error. `:trace on` in
+    /// the repl writes there too.
+    #[arg(long, value_name = "FILE")]
+    trace_file: Option<PathBuf>,
+
+    /// How to print errors, warnings and notes.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t)]
+    message_format: MessageFormat,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Run a language server over stdio, for editors. It uses the same
+    /// identifier policy as everything else.
+    Lsp,
+    /// Lay out programs the same way every time. Files are rewritten in
+    /// place, or standard input is written to standard output.
+    Fmt {
+        /// The files to format.
+        files: Vec<PathBuf>,
+
+        /// Don't change anything, just fail if a file isn't formatted.
+        #[arg(long)]
+        check: bool,
+    },
 }
 
 #[derive(Debug, Default, Clone, Copy, ValueEnum, PartialEq, Eq, PartialOrd, Ord)]
-pub enum Filter {
-    Off,
+pub enum Hand {
+    /// Filter out left-hand letters.
     #[default]
-    Qwerty,
-    Dvorak,
-    Colemak,
+    Right,
+    /// Filter out right-hand letters.
+    Left,
+    /// Reject identifiers unless their letters alternate between hands.
+    Alternating,
 }
 
-impl From<Filter> for BlockList {
-    fn from(val: Filter) -> Self {
-        BlockList::from_disallowed(match val {
-            Filter::Off => "",
-            Filter::Qwerty => "qwertasdfgzxczvb123456",
-            Filter::Dvorak => "aoeuptqjkx123456",
-            Filter::Colemak => "qwfpgarstdzxcvb12345",
-        })
+fn relation_file(arg: &str) -> Result<(String, OsString), String> {
+    match arg.split_once('=') {
+        Some((relation, file)) if !relation.is_empty() && !file.is_empty() => {
+            Ok((relation.into(), file.into()))
+        }
+        _ => Err("expected RELATION=FILE".into()),
     }
 }
 
+fn seconds(arg: &str) -> Result<Duration, String> {
+    arg.parse()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| "expected a number of seconds".into())
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
+    let reporter = Reporter::new(args.message_format, args.deny_warnings);
+
+    match run(args, &reporter) {
+        Err(error) if reporter.format() == MessageFormat::Json => {
+            reporter.emit(&*error);
+            std::process::exit(1);
+        }
+        result => result,
+    }
+}
 
-    let mut data = DataSet::default();
+fn run(args: Args, reporter: &Reporter) -> Result<()> {
+    if let Some(Command::Fmt { files, check }) = &args.command {
+        return format_files(files, *check);
+    }
+
+    let layouts = layout::catalog(args.layouts.as_deref())?;
 
-    let blocked = args.filter.into();
+    if args.list_layouts {
+        for layout in &layouts {
+            println!(
+                "{:<10} left: {:<24} right: {}",
+                layout.name, layout.left, layout.right
+            );
+        }
+        return Ok(());
+    }
+
+    let layout = layout::find(&layouts, &args.filter)?;
+    let policy: Rc<dyn IdentifierPolicy> = match args.hand {
+        Hand::Right => Rc::new(BlockList::from_disallowed(layout.left.clone())),
+        Hand::Left => Rc::new(BlockList::from_disallowed(layout.right.clone())),
+        Hand::Alternating => Rc::new(Alternating::new(layout.left.clone(), layout.right.clone())),
+    };
+
+    if let Some(Command::Lsp) = &args.command {
+        return lsp::serve(policy);
+    }
+
+    let mut data = match &args.db {
+        Some(db) => DataSet::open(db)?,
+        None => DataSet::default(),
+    };
+
+    if let Some(threads) = args.threads {
+        data.set_threads(threads);
+    }
+
+    let trace_file = match &args.trace_file {
+        Some(path) => Some(fs::File::create(path).into_diagnostic()?),
+        None => None,
+    };
+    if args.trace {
+        data.set_trace(Some(trace(trace_file.as_ref(), &args.trace_relation)?));
+    }
+
+    let mut limits = Limits {
+        time: args.timeout,
+        facts: args.max_facts,
+        iterations: args.max_iterations,
+        cancel: None,
+    };
+
+    for (relation, file) in &args.input {
+        data.load(relation, file)?;
+    }
 
     if let Some(filename) = args.filename.as_deref() {
         let input = fs::read_to_string(filename).into_diagnostic()?;
 
-        let program = Program::parse(input.as_str(), blocked).map_err(|errors| {
-            Report::from(errors)
-                .with_source_code(NamedSource::new(filename.to_string_lossy(), input))
-        })?;
+        let mut diagnostics = Diagnostics::default();
+        let program =
+            Program::parse_with_diagnostics(input.as_str(), policy.clone(), &mut diagnostics);
+        if let (Some(program), false) = (&program, diagnostics.has_errors()) {
+            data.check(program, &mut diagnostics);
+        }
+        reporter.report(diagnostics, &filename.to_string_lossy(), &input)?;
+        let mut program = program
+            .expect("parse errors are reported")
+            .with_name(filename.to_string_lossy());
+        // `.input` and `.output` files are next to the program.
+        if let Some(directory) = Path::new(filename).parent() {
+            program = program.with_directory(directory);
+        }
 
-        data.program(&program);
+        data.program(&program)?;
 
         if args.query.is_none() {
             println!(
@@ -74,57 +271,249 @@ fn main() -> Result<()> {
         }
     }
 
-    if let Some(query) = args.query {
-        let query = Query::parse(query.as_str(), blocked).map_err(|errors| {
-            Report::from(errors).with_source_code(NamedSource::new("--query", query))
-        })?;
+    data.commit()?;
 
-        data.run();
-        print_query_answers(&query, &mut data);
-        Ok(())
+    if let Some(query) = args.query {
+        let mut diagnostics = Diagnostics::default();
+        let parsed =
+            Query::parse_with_diagnostics(query.as_str(), policy.clone(), &mut diagnostics);
+        reporter.report(diagnostics, "--query", &query)?;
+        let query = parsed.expect("parse errors are reported");
+
+        print_query_answers(&query, &mut data, args.top_down, &limits)?;
+        write_outputs(&mut data, &args.output, &limits)?;
+        if args.profile {
+            eprint!("{}", data.stats());
+        }
+        Ok(check_constraints(&mut data, &limits)?)
     } else if args.repl || args.filename.is_none() {
-        repl(data, blocked)
+        // Control-C at the prompt is handled by rustyline, but while rules
+        // are being run it cancels them instead of quitting.
+        let cancel = Cancel::new();
+        let handler = cancel.clone();
+        ctrlc::set_handler(move || handler.cancel()).into_diagnostic()?;
+        limits.cancel = Some(cancel);
+
+        // Broken constraints are worth knowing about, but the repl is a good
+        // place to find out why.
+        after_repl_step(&mut data, &args.output, &limits, reporter);
+
+        repl(
+            data,
+            policy,
+            reporter,
+            args.top_down,
+            &limits,
+            &args.output,
+            trace_file.as_ref(),
+        )
     } else {
-        data.run();
+        data.run_with_limits(&limits)?;
+        write_outputs(&mut data, &args.output, &limits)?;
         println!("{data}");
+        if args.profile {
+            eprint!("{}", data.stats());
+        }
+        Ok(check_constraints(&mut data, &limits)?)
+    }
+}
+
+/// Run the rules, if the data set has any constraints, and fail if some facts
+/// match one of them. The errors point into the programs the constraints
+/// came from.
+fn check_constraints(data: &mut DataSet, limits: &Limits) -> Result<(), Error> {
+    if !data.has_constraints() {
+        return Ok(());
+    }
+
+    data.run_with_limits(limits)?;
+    let mut diagnostics = Diagnostics::default();
+    data.check_constraints(&mut diagnostics);
+
+    let (errors, _) = diagnostics.into_parts();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into())
+    }
+}
+
+/// Trace to `file` if there is one, or standard error otherwise.
+fn trace(file: Option<&fs::File>, relations: &[String]) -> Result<Trace> {
+    let trace = match file {
+        Some(file) => Trace::new(file.try_clone().into_diagnostic()?),
+        None => Trace::new(io::stderr()),
+    };
+    Ok(trace.relations(relations))
+}
+
+/// Format some files, or standard input if there aren't any.
+fn format_files(files: &[PathBuf], check: bool) -> Result<()> {
+    let mut sources = Vec::new();
+    if files.is_empty() {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input).into_diagnostic()?;
+        sources.push(("<stdin>".to_string(), None, input));
+    }
+    for file in files {
+        let input = fs::read_to_string(file).into_diagnostic()?;
+        sources.push((file.display().to_string(), Some(file), input));
+    }
+
+    let mut unformatted = Vec::new();
+    for (name, file, input) in sources {
+        let formatted = datalog::format(&input)
+            .map_err(|error| error.with_source_code(&name, input.as_str()))?;
+
+        match file {
+            _ if check => {
+                if formatted != input {
+                    unformatted.push(name);
+                }
+            }
+            Some(file) => {
+                if formatted != input {
+                    fs::write(file, formatted).into_diagnostic()?;
+                }
+            }
+            None => print!("{formatted}"),
+        }
+    }
+
+    if unformatted.is_empty() {
         Ok(())
+    } else {
+        let files = unformatted.join(", ");
+        Err(Error::new(ErrorKind::Unformatted, format!("not formatted: {files}")).into())
     }
 }
 
-fn print_query_answers(query: &Query, data: &mut DataSet) {
-    let answers = data.query(query);
+/// Write the `.output` directives, and any `--output` relations, running the
+/// rules first if there are any.
+fn write_outputs(
+    data: &mut DataSet,
+    outputs: &[(String, OsString)],
+    limits: &Limits,
+) -> Result<()> {
+    if data.has_outputs() || !outputs.is_empty() {
+        data.run_with_limits(limits)?;
+    }
+    data.write_outputs()?;
+
+    for (relation, file) in outputs {
+        data.save(relation, file)?;
+    }
 
-    if answers.is_empty() {
+    Ok(())
+}
+
+fn print_query_answers(
+    query: &Query,
+    data: &mut DataSet,
+    top_down: bool,
+    limits: &Limits,
+) -> Result<(), Error> {
+    let answers: Box<dyn Iterator<Item = Result<_, Error>>> = if top_down {
+        Box::new(data.query_tabled_with_limits(query, limits))
+    } else {
+        data.run_with_limits(limits)?;
+        Box::new(data.query(query).map(Ok))
+    };
+    let mut answers = answers.peekable();
+
+    if answers.peek().is_none() {
         println!("<no answers>");
     } else {
         for answer in answers {
-            println!("{}", answer);
+            if limits.cancel.as_ref().is_some_and(Cancel::is_cancelled) {
+                return Err(Error::new(ErrorKind::Cancelled, "cancelled"));
+            }
+            println!("{}", answer?);
         }
     }
+
+    Ok(())
 }
 
-fn repl(mut data: DataSet, blocked: BlockList) -> Result<()> {
+fn repl(
+    mut data: DataSet,
+    policy: Rc<dyn IdentifierPolicy>,
+    reporter: &Reporter,
+    top_down: bool,
+    limits: &Limits,
+    outputs: &[(String, OsString)],
+    trace_file: Option<&fs::File>,
+) -> Result<()> {
     let mut rl = Editor::<()>::new().into_diagnostic()?;
     let mut line_count = 1;
-    let handler = GraphicalReportHandler::new();
 
     loop {
         let line = rl.readline(">> ");
-        let mut buf = String::new();
 
         match line {
+            Ok(line)
+                if line.trim_start().starts_with(':') && !line.trim_start().starts_with(":-") =>
+            {
+                let words: Vec<&str> = line.split_whitespace().collect();
+                let result = match &words[..] {
+                    [":stats"] => {
+                        print!("{}", data.stats());
+                        Ok(())
+                    }
+                    [":trace", "on", relations @ ..] => {
+                        let relations: Vec<String> =
+                            relations.iter().map(|r| r.to_string()).collect();
+                        trace(trace_file, &relations).map(|trace| data.set_trace(Some(trace)))
+                    }
+                    [":trace", "off"] => {
+                        data.set_trace(None);
+                        Ok(())
+                    }
+                    _ => Err(miette::miette!(
+                        "unknown command `{}`, the commands are `:stats`, \
+                         `:trace on [RELATION...]` and `:trace off`",
+                        line.trim()
+                    )),
+                };
+                if let Err(error) = result {
+                    reporter.emit(&*error);
+                }
+                line_count += 1;
+            }
+
             Ok(line) => {
-                if let Err(error) = repl_step(&line, &mut data, blocked) {
+                let name = format!("<repl:{line_count}>");
+                let mut diagnostics = Diagnostics::default();
+                let syntax = Repl::parse_with_diagnostics(&line, policy.clone(), &mut diagnostics)
+                    .map(|syntax| match syntax {
+                        Repl::Program(program) => Repl::Program(program.with_name(&name)),
+                        query => query,
+                    });
+                if let Some(Repl::Program(program)) = &syntax {
+                    data.check(program, &mut diagnostics);
+                }
+
+                if let Some(cancel) = &limits.cancel {
+                    cancel.reset();
+                }
+
+                let result = reporter.report(diagnostics, &name, &line).and_then(|()| {
+                    repl_step(
+                        syntax.expect("parse errors are reported"),
+                        &mut data,
+                        top_down,
+                        limits,
+                    )
+                });
+
+                if let Err(error) = result {
                     if line == "quit" || line == "exit" {
                         println!("hint: use control-d to leave");
                     }
 
-                    buf.clear();
-                    let diagnostic = error
-                        .with_source_code(NamedSource::new(format!("<repl:{line_count}>"), line));
-                    let _ = handler.render_report(&mut buf, &diagnostic as &dyn Diagnostic);
-
-                    println!("{}", buf);
+                    reporter.emit(&error.with_source_code(name, line));
+                } else {
+                    after_repl_step(&mut data, outputs, limits, reporter);
                 }
 
                 line_count += 1;
@@ -137,6 +526,7 @@ fn repl(mut data: DataSet, blocked: BlockList) -> Result<()> {
 
             // Control-D quits
             Err(ReadlineError::Eof) => {
+                data.compact()?;
                 println!("goodbye!");
                 return Ok(());
             }
@@ -148,15 +538,37 @@ fn repl(mut data: DataSet, blocked: BlockList) -> Result<()> {
     }
 }
 
-fn repl_step(input: &str, data: &mut DataSet, blocked: BlockList) -> Result<(), Error> {
-    let syntax = Repl::parse(input, blocked).map_err(Error::from)?;
+/// Check the constraints and write the outputs again, since the line just
+/// entered may have changed the facts. Problems are reported without leaving
+/// the repl.
+fn after_repl_step(
+    data: &mut DataSet,
+    outputs: &[(String, OsString)],
+    limits: &Limits,
+    reporter: &Reporter,
+) {
+    // These already point at wherever each constraint came from, which
+    // usually isn't the line just entered.
+    if let Err(error) = check_constraints(data, limits) {
+        reporter.emit(&error);
+    }
+    if let Err(error) = write_outputs(data, outputs, limits) {
+        reporter.emit(&*error);
+    }
+}
 
+fn repl_step(
+    syntax: Repl,
+    data: &mut DataSet,
+    top_down: bool,
+    limits: &Limits,
+) -> Result<(), Error> {
     match syntax {
-        Repl::Program(p) => data.program(&p),
-        Repl::Query(query) => {
-            data.run();
-            print_query_answers(&query, data);
+        Repl::Program(p) => {
+            data.program(&p)?;
+            data.commit()?;
         }
+        Repl::Query(query) => print_query_answers(&query, data, top_down, limits)?,
     }
 
     Ok(())
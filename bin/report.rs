@@ -0,0 +1,148 @@
+//! Printing diagnostics, either for people or for tools.
+
+use clap::ValueEnum;
+use miette::{Diagnostic, GraphicalReportHandler, LabeledSpan, Severity, SourceCode};
+use serde_json::{json, Value};
+
+use datalog::{Diagnostics, Error, ErrorKind};
+
+#[derive(Debug, Default, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// Rendered with the source code, for reading.
+    #[default]
+    Human,
+    /// One JSON object per line, for editors and CI.
+    Json,
+}
+
+/// Prints diagnostics to stderr.
+pub struct Reporter {
+    format: MessageFormat,
+    deny_warnings: bool,
+    handler: GraphicalReportHandler,
+}
+
+impl Reporter {
+    pub fn new(format: MessageFormat, deny_warnings: bool) -> Self {
+        Reporter {
+            format,
+            deny_warnings,
+            handler: GraphicalReportHandler::new(),
+        }
+    }
+
+    pub fn format(&self) -> MessageFormat {
+        self.format
+    }
+
+    /// Print a diagnostic, along with the ones related to it.
+    pub fn emit(&self, diagnostic: &dyn Diagnostic) {
+        match self.format {
+            MessageFormat::Human => {
+                let mut buf = String::new();
+                let _ = self.handler.render_report(&mut buf, diagnostic);
+                eprintln!("{}", buf);
+            }
+            MessageFormat::Json => {
+                eprintln!("{}", to_json(diagnostic));
+                for related in diagnostic.related().into_iter().flatten() {
+                    self.emit(related);
+                }
+            }
+        }
+    }
+
+    /// Print the warnings and notes about some input. This fails if there are
+    /// any errors, or any warnings when they're denied.
+    pub fn report(&self, diagnostics: Diagnostics, name: &str, source: &str) -> Result<(), Error> {
+        let warnings = diagnostics.warning_count();
+        let (errors, lints) = diagnostics.with_source_code(name, source).into_parts();
+
+        for lint in &lints {
+            self.emit(lint);
+        }
+
+        if !errors.is_empty() {
+            Err(Error::from(errors))
+        } else if self.deny_warnings && warnings > 0 {
+            let s = if warnings == 1 { "" } else { "s" };
+            Err(Error::new(
+                ErrorKind::DeniedWarnings,
+                format!("{warnings} warning{s} in {name}, and warnings are denied"),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// What kind of diagnostic this is: `error`, `warning` or `note`.
+pub fn severity(diagnostic: &dyn Diagnostic) -> &'static str {
+    match diagnostic.severity().unwrap_or(Severity::Error) {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Advice => "note",
+    }
+}
+
+/// The message of a diagnostic, without the severity in front.
+pub fn message(diagnostic: &dyn Diagnostic) -> String {
+    let message = diagnostic.to_string();
+    match message.strip_prefix(&format!("{}: ", severity(diagnostic))) {
+        Some(stripped) => stripped.to_string(),
+        None => message,
+    }
+}
+
+// Like `{"code": "datalog::syntax", "severity": "error", "message": "syntax
+// error", "file": "family.dl", "spans": [...], "help": null}`. Lines and
+// columns count from 1, and byte offsets from 0.
+fn to_json(diagnostic: &dyn Diagnostic) -> Value {
+    let severity = severity(diagnostic);
+    let message = message(diagnostic);
+
+    let source = diagnostic.source_code();
+    let file = source.and_then(|source| {
+        let contents = source.read_span(&(0, 0).into(), 0, 0).ok()?;
+        contents.name().map(str::to_string)
+    });
+
+    let spans: Vec<Value> = diagnostic
+        .labels()
+        .into_iter()
+        .flatten()
+        .map(|label| span_json(&label, source))
+        .collect();
+
+    json!({
+        "code": diagnostic.code().map(|code| code.to_string()),
+        "severity": severity,
+        "message": message,
+        "file": file,
+        "spans": spans,
+        "help": diagnostic.help().map(|help| help.to_string()),
+    })
+}
+
+fn span_json(label: &LabeledSpan, source: Option<&dyn SourceCode>) -> Value {
+    let start = label.offset();
+    let end = start + label.len();
+
+    // The line and column of a byte offset.
+    let position = |offset: usize| {
+        let contents = source?.read_span(&(offset, 0).into(), 0, 0).ok()?;
+        Some((contents.line() + 1, contents.column() + 1))
+    };
+    let (line, column) = position(start).unzip();
+    let (end_line, end_column) = position(end).unzip();
+
+    json!({
+        "label": label.label(),
+        "start": start,
+        "end": end,
+        "line": line,
+        "column": column,
+        "end_line": end_line,
+        "end_column": end_column,
+    })
+}
@@ -0,0 +1,100 @@
+//! How much memory a big graph takes up in a data set, compared with keeping
+//! the same tuples in a `BTreeSet<Vec<usize>>`, which is how relations used to
+//! be stored.
+//!
+//! ```text
+//! cargo run --release --example memory -- [EDGES] [NODES]
+//! ```
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::BTreeSet,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use datalog::DataSet;
+
+/// Keeps count of the bytes allocated and not yet freed.
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// The bytes still allocated after making something, and the thing.
+fn measure<T>(make: impl FnOnce() -> T) -> (usize, T) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let value = make();
+    (ALLOCATED.load(Ordering::Relaxed) - before, value)
+}
+
+/// A random graph, the same every time.
+fn edges(edges: usize, nodes: usize) -> Vec<(usize, usize)> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % nodes as u64) as usize
+    };
+    (0..edges).map(|_| (random(), random())).collect()
+}
+
+fn megabytes(bytes: usize) -> String {
+    format!("{:.1} MB", bytes as f64 / 1_000_000.0)
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1).map(|arg| arg.parse().unwrap());
+    let edge_count = args.next().unwrap_or(1_000_000);
+    let node_count = args.next().unwrap_or(100_000);
+
+    let edges = edges(edge_count, node_count);
+    let names: Vec<String> = (0..node_count).map(|n| format!("n{n}")).collect();
+
+    // The names cost the same either way, so count them on their own.
+    let (names_only, _data) = measure(|| {
+        let mut data = DataSet::default();
+        for name in &names {
+            data.insert_fact("node", &[name]);
+        }
+        data
+    });
+
+    let (graph, data) = measure(|| {
+        let mut data = DataSet::default();
+        for (from, to) in &edges {
+            data.insert_fact("edge", &[&names[*from], &names[*to]]);
+        }
+        data
+    });
+
+    let (tree, set) = measure(|| {
+        edges
+            .iter()
+            .map(|(from, to)| vec![*from, *to])
+            .collect::<BTreeSet<_>>()
+    });
+
+    println!("{} edges between {node_count} nodes", set.len());
+    println!("BTreeSet<Vec<usize>>: {}", megabytes(tree));
+    println!(
+        "data set:             {} (about {} of it names)",
+        megabytes(graph),
+        megabytes(names_only),
+    );
+    assert_eq!(data.len(), set.len());
+}
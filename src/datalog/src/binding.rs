@@ -2,6 +2,7 @@
 /// the key, so the keys must be dense and inserted in order.
 
 #[derive(Debug, Default, PartialEq, PartialOrd, Ord, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct Binding(Vec<usize>);
 
 impl From<Vec<usize>> for Binding {
@@ -0,0 +1,47 @@
+//! Building facts and rules in Rust, without going through the parser.
+//!
+//! ```
+//! use datalog::{
+//!     build::{atom, var},
+//!     DataSet,
+//! };
+//!
+//! let mut data = DataSet::default();
+//! data.insert_fact("parent", &["padme", "luke"]);
+//! data.add_rule(
+//!     atom("ancestor", [var("X"), var("Y")]).when([atom("parent", [var("X"), var("Y")])]),
+//! )
+//! .unwrap();
+//!
+//! data.run();
+//! assert_eq!(data.len(), 2);
+//! ```
+//!
+//! Names built this way are used exactly as given, they're never checked to
+//! see if they look like constants or variables, and they're not filtered by
+//! a [`BlockList`][crate::BlockList].
+
+pub use crate::parser::{Atom, Const, Relation, Rule, Term, Var};
+
+/// An atom like `parent(X, luke)`, for use in a [`Rule`].
+pub fn atom(relation: &str, terms: impl IntoIterator<Item = Term>) -> Atom {
+    Atom(Relation(relation.into()), terms.into_iter().collect())
+}
+
+/// A variable term, like `X`.
+pub fn var(name: &str) -> Term {
+    Term::Var(Var(name.into()))
+}
+
+/// A constant term, like `luke`.
+pub fn constant(name: &str) -> Term {
+    Term::Const(Const(name.into()))
+}
+
+impl Atom {
+    /// Make a rule with this atom as the head, which holds when every atom in
+    /// the body holds.
+    pub fn when(self, body: impl IntoIterator<Item = Atom>) -> Rule {
+        Rule(self, body.into_iter().collect())
+    }
+}
@@ -31,8 +31,8 @@ impl Counter {
             length,
             max,
             cursor: 0,
-            // e.g. length 3, max 10 should be 10^4, since 0 - 9,999 is 10,000 digits
-            end: max.pow(length as u32 + 1),
+            // e.g. length 3, max 10 should be 10^3, since 0 - 999 is 1,000 numbers
+            end: max.pow(length as u32),
         }
     }
 
@@ -91,6 +91,12 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn no_repeats() {
+        let counter = Counter::new(2, 3);
+        assert_eq!(counter.count(), 9);
+    }
+
     #[test]
     fn counter_longer() {
         use std::collections::BTreeSet as Set;
@@ -1,54 +1,131 @@
-use std::collections::BTreeSet;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    time::Instant,
+};
 
 use crate::{
-    name_pool::NamePool,
+    name_pool::{kind, ConstantId, Names, RelationId},
     parser::{
-        Const, Fact, Program, Query as QuerySyntax, Relation as RelationSyntax, Rule as RuleSyntax,
-        Statement,
+        Const, Fact, Input, Output, Program, Query as QuerySyntax, Relation as RelationSyntax,
+        Rule as RuleSyntax, Statement,
     },
+    Error,
 };
 
 mod answer;
+mod check;
+mod constraints;
+mod foreign;
 mod goal;
+mod inspect;
+mod io;
+mod limits;
+mod parallel;
 mod query;
+mod relation;
 mod rule;
+#[cfg(feature = "serde")]
+mod snapshot;
+mod solve;
+mod stats;
+#[cfg(feature = "serde")]
+mod storage;
+mod tabled;
+mod trace;
 
-pub use self::answer::Answer;
-use self::{query::Query, rule::Rule};
+pub use self::{
+    answer::Answer,
+    foreign::Mode,
+    inspect::RelationInfo,
+    limits::{Cancel, Limits},
+    stats::{IterationStats, RuleStats, Stats},
+    trace::Trace,
+};
+use self::{
+    constraints::Constraint,
+    foreign::ForeignPredicate,
+    limits::Stop,
+    parallel::{Found, Threads},
+    query::Query,
+    relation::Relation,
+    rule::Rule,
+};
 
-pub(self) type Set<T> = BTreeSet<T>;
+type Set<T> = BTreeSet<T>;
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
-pub(self) struct Tuple(Vec<usize>);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Tuple(Vec<u32>);
 
 impl From<Vec<usize>> for Tuple {
     fn from(value: Vec<usize>) -> Self {
-        Tuple(value)
+        Tuple(value.into_iter().map(relation::id).collect())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) enum Term {
     Constant(usize),
     Variable(usize),
 }
 
 #[derive(Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DataSet {
     last_len: usize,
     rules: Vec<Rule>,
 
-    /// The names of all the relations in this data set.
-    pub(self) relation_names: NamePool,
-    /// The names of all the constant values in this data set.
-    pub(self) constant_names: NamePool,
+    /// Bodies which no facts should ever match, checked with
+    /// [`DataSet::check_constraints`].
+    constraints: Vec<Constraint>,
 
-    /// The names of variables seen in queries.
-    pub(self) variable_names: NamePool,
+    /// The names of all the relations and constant values in this data set,
+    /// and of the variables seen in queries.
+    pub(self) names: Names,
 
     /// A relation is a set of tuples which satisfy some predicate. The index
-    /// corresponds to relation_names.
-    pub(self) relations: Vec<Set<Tuple>>,
+    /// is the relation's id in `names`.
+    pub(self) relations: Vec<Relation>,
+
+    /// The relations to write out with [`DataSet::write_outputs`], and where.
+    /// Like `bound`, this belongs to the program it came from, and its paths
+    /// are relative to that program, so it isn't saved.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    outputs: Vec<(usize, PathBuf)>,
+
+    /// The most steps [`DataSet::run`] takes, from a `.bound` directive.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    bound: Option<usize>,
+
+    /// The relations which are foreign predicates, instead of sets of tuples.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(self) foreign: BTreeMap<usize, ForeignPredicate>,
+
+    /// Where rules are applied, set with [`DataSet::set_threads`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    threads: Threads,
+
+    /// What running the rules has done, for [`DataSet::stats`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    stats: stats::Counters,
+
+    /// The facts derived since [`DataSet::run_with_limits`] started, so they
+    /// can be taken out again if it gives up.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    undo: Option<Vec<(usize, Box<[u32]>)>>,
+
+    /// Where to write what running the rules does, set with
+    /// [`DataSet::set_trace`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    trace: Option<Trace>,
+
+    /// Where facts and rules are saved, for data sets opened with
+    /// [`DataSet::open`].
+    #[cfg(feature = "serde")]
+    #[serde(skip)]
+    storage: Option<storage::Storage>,
 }
 
 /// Public interface for working with the data set.
@@ -70,33 +147,145 @@ impl DataSet {
 
     /// Applies the known rules until there are no more facts to discover.
     pub fn run(&mut self) {
-        while self.is_dirty() {
-            self.last_len = self.len();
-            self.step();
-        }
+        // Without any limits, nothing can stop it early.
+        let _ = self.run_until(&Limits::default(), None);
     }
 }
 
 /// Syntax-based public methods
 impl DataSet {
     /// Add the facts and rules from a [`Program`] into this data set.
-    pub fn program(&mut self, program: &Program) {
-        for statement in program.statements() {
+    ///
+    /// Any `.input` directives are loaded with [`DataSet::load`], and any
+    /// `.output` directives are remembered for [`DataSet::write_outputs`].
+    /// Their files are relative to the program's
+    /// [directory](Program::with_directory), or to the current working
+    /// directory if it doesn't have one.
+    pub fn program(&mut self, program: &Program) -> Result<(), Error> {
+        for (i, statement) in program.statements().iter().enumerate() {
             match statement {
                 Statement::Fact(fact) => self.fact(fact),
-                Statement::Rule(rule) => self.rule(rule),
+                Statement::Rule(rule) => self.rule(rule).map_err(|error| {
+                    let span = program.span(i);
+                    error.with_labeled_span(span.start, span.len(), "in this rule")
+                })?,
+                Statement::Input(Input(RelationSyntax(name), path)) => {
+                    self.load(name, program.path(path))?
+                }
+                Statement::Output(Output(RelationSyntax(name), path)) => {
+                    let output = (self.declare_relation(name), program.path(path));
+                    // Loading the same program twice shouldn't write it twice.
+                    if !self.outputs.contains(&output) {
+                        self.outputs.push(output);
+                    }
+                }
+                Statement::Bound(steps) => self.bound = Some(*steps),
+                Statement::Constraint(body) => self.constraint(Constraint {
+                    body: body.clone(),
+                    name: program.name().clone(),
+                    source: program.source().clone(),
+                    span: program.span(i),
+                }),
+                // These only matter to the diagnostics.
+                Statement::Allow(_) => {}
             }
         }
+
+        Ok(())
     }
 
-    /// Run a [`Query`][`crate::parser::Query`] against this data set.
+    /// Run a [`Query`][`crate::parser::Query`] against this data set, returning
+    /// an iterator over the answers as they're found.
     ///
     /// Note that this doesn't call [`Dataset::run`].
-    pub fn query(&mut self, query: &QuerySyntax) -> Vec<Answer> {
+    pub fn query(&mut self, query: &QuerySyntax) -> impl Iterator<Item = Answer> + '_ {
+        let QuerySyntax(sub_goals) = query;
+        let q = Query::new(sub_goals, self);
+
+        q.answers(self)
+    }
+
+    /// Like [`DataSet::query`], but the answers are worked out top-down from
+    /// the query, so only the rules and facts it needs are looked at, and
+    /// [`DataSet::run`] isn't needed first. Recursive rules are fine, since
+    /// the answers for each goal are tabled as they're found.
+    ///
+    /// This gives the same answers as calling [`DataSet::run`] and then
+    /// [`DataSet::query`], though maybe in a different order.
+    ///
+    /// ```
+    /// # use datalog::{BlockList, DataSet, Program, Query};
+    /// let program = "
+    ///     parent(xerces, brooke). parent(brooke, damocles).
+    ///     ancestor(X, Y) :- parent(X, Y).
+    ///     ancestor(X, Y) :- ancestor(X, Z), parent(Z, Y).
+    /// ";
+    /// let mut data = DataSet::default();
+    /// data.program(&Program::parse(program, BlockList::OFF).unwrap()).unwrap();
+    ///
+    /// let query = Query::parse("ancestor(xerces, X)", BlockList::OFF).unwrap();
+    /// let mut answers: Vec<_> = data.query_tabled(&query).map(|a| a["X"].to_string()).collect();
+    /// answers.sort();
+    /// assert_eq!(answers, ["brooke", "damocles"]);
+    /// ```
+    pub fn query_tabled(&mut self, query: &QuerySyntax) -> impl Iterator<Item = Answer> + '_ {
+        let QuerySyntax(sub_goals) = query;
+        let q = Query::new(sub_goals, self);
+
+        // Without any limits, nothing can stop it early.
+        q.tabled_answers(self, None).map_while(Result::ok)
+    }
+
+    /// Like [`DataSet::query_tabled`], but give up with an error if working
+    /// out the answers goes over any of the `limits`. Then the error is the
+    /// last item, and the answers before it may not be all of them.
+    pub fn query_tabled_with_limits<'a>(
+        &'a mut self,
+        query: &QuerySyntax,
+        limits: &'a Limits,
+    ) -> impl Iterator<Item = Result<Answer, Error>> + 'a {
         let QuerySyntax(sub_goals) = query;
         let q = Query::new(sub_goals, self);
 
-        self.search(q)
+        q.tabled_answers(self, Some(limits))
+            .map(|answer| answer.map_err(|reason| reason.error(limits)))
+    }
+}
+
+/// Builder methods, for adding facts and rules without going through the
+/// parser. See [`crate::build`].
+impl DataSet {
+    /// Add a single fact, like `parent(padme, luke)`. This is false if the
+    /// fact was already known.
+    pub fn insert_fact(&mut self, relation: &str, constants: &[&str]) -> bool {
+        let tuple = Tuple(
+            constants
+                .iter()
+                .map(|c| relation::id(self.names.add::<kind::Constant>(c).index()))
+                .collect(),
+        );
+
+        let rel = self.declare_relation(relation);
+        if !self.relations[rel].insert(&tuple.0) {
+            // Already known, so there's nothing new to save.
+            return false;
+        }
+
+        #[cfg(feature = "serde")]
+        if let Some(storage) = &mut self.storage {
+            let constants = constants.iter().map(|c| c.to_string()).collect();
+            storage.push(storage::Entry::Fact(relation.into(), constants));
+        }
+
+        true
+    }
+
+    /// Add a rule, usually made with [`Atom::when`][crate::build::Atom::when].
+    ///
+    /// This fails if the rule uses a foreign predicate the wrong way, see
+    /// [`DataSet::register_predicate`].
+    pub fn add_rule(&mut self, rule: RuleSyntax) -> Result<(), Error> {
+        self.rule(&rule)
     }
 }
 
@@ -105,7 +294,7 @@ impl DataSet {
     /// Makes sure some relation name exists in the data set, adding an empty
     /// relation for it and adding the name if it doesn't.
     fn declare_relation(&mut self, name: &str) -> usize {
-        let rel = self.relation_names.add_name(name);
+        let rel = self.names.add::<kind::Relation>(name).index();
         if rel == self.relations.len() {
             self.relations.push(Default::default());
         }
@@ -113,50 +302,119 @@ impl DataSet {
     }
 
     /// Takes a step in the fact-expanding loop, used by [`DataSet::run`].
-    fn step(&mut self) {
-        for i in 0..self.rules.len() {
-            let rule = &self.rules[i];
-            let new_facts = rule.step(self);
-            self.relations[rule.relation()].extend(new_facts);
+    ///
+    /// Every rule is applied to the facts known at the start of the step, and
+    /// what they find is added afterwards, in rule order. So the rules can be
+    /// applied at once, and new constants get the same ids either way. If the
+    /// step is stopped, nothing is added.
+    fn step(&mut self, stop: &Stop) -> Result<(), limits::Reason> {
+        let start = Instant::now();
+        let known = relation::id(self.constants_count());
+        let lens: Vec<usize> = self.relations.iter().map(Relation::len).collect();
+
+        let found = self.apply_rules(stop);
+        if let Some(reason) = stop.reason() {
+            return Err(reason);
+        }
+
+        let mut last_rule = None;
+        for Found {
+            rule,
+            relation,
+            derived: mut found,
+            time,
+        } in found
+        {
+            let counts = self.stats.rule(rule);
+            if last_rule != Some(rule) {
+                counts.fired += 1;
+                last_rule = Some(rule);
+            }
+            counts.considered += found.considered;
+            counts.time += time;
+
+            let new_ids = found
+                .new_constants
+                .iter()
+                .map(|name| relation::id(self.names.add::<kind::Constant>(name).index()))
+                .collect::<Vec<_>>();
+            let rename = |c: u32| match c.checked_sub(known) {
+                Some(new) => new_ids[new as usize],
+                None => c,
+            };
+            let text = (!found.bindings.is_empty())
+                .then(|| self.rule_syntax(&self.rules[rule]).to_string());
+
+            let mut derived = 0;
+            for tuple in found.facts {
+                let binding = found.bindings.remove(&tuple);
+                let tuple: Vec<u32> = tuple.0.into_iter().map(rename).collect();
+                if !self.relations[relation].insert(&tuple) {
+                    continue;
+                }
+                derived += 1;
+                if let Some(undo) = &mut self.undo {
+                    undo.push((relation, tuple.as_slice().into()));
+                }
+
+                if let (Some(text), Some(binding)) = (&text, binding) {
+                    let binding = binding
+                        .iter()
+                        .map(|(_, c)| rename(relation::id(c)) as usize)
+                        .collect::<Vec<_>>()
+                        .into();
+                    self.trace_fact(&self.rules[rule], text, &tuple, &binding);
+                }
+            }
+            self.stats.rule(rule).derived += derived;
         }
+
+        let growth = lens
+            .into_iter()
+            .enumerate()
+            .map(|(rel, len)| (rel, self.relations[rel].len() - len))
+            .filter(|(_, new)| *new > 0)
+            .collect();
+        self.stats.iterations.push((start.elapsed(), growth));
+
+        Ok(())
     }
 
     /// The number of constant names in this data set.
     pub(self) fn constants_count(&self) -> usize {
-        self.constant_names.len()
-    }
-
-    fn search(&self, query: Query) -> Vec<Answer> {
-        query
-            .bindings(self)
-            .iter()
-            .map(|binding| Answer::new(binding, query.variables(), self))
-            .collect()
+        self.names.len::<kind::Constant>()
     }
 }
 
 /// Syntax helpers
 impl DataSet {
-    fn rule(&mut self, rule: &RuleSyntax) {
-        let RuleSyntax(head, clauses) = rule;
+    // A rule which is already known is skipped, so that loading the same
+    // program into a data set opened with `DataSet::open` again doesn't pile
+    // up copies of its rules.
+    fn rule(&mut self, syntax: &RuleSyntax) -> Result<(), Error> {
+        let RuleSyntax(head, clauses) = syntax;
+        self.check_foreign(head, clauses)?;
 
         let rule = Rule::new(head, clauses, self);
+        if self.rules.contains(&rule) {
+            return Ok(());
+        }
+
+        #[cfg(feature = "serde")]
+        if let Some(storage) = &mut self.storage {
+            storage.push(storage::Entry::Rule(syntax.clone()));
+        }
         self.rules.push(rule);
+        Ok(())
     }
 
     fn fact(&mut self, fact: &Fact) {
         let Fact(RelationSyntax(name), constants) = fact;
-
-        let tuple = Tuple(
-            constants
-                .iter()
-                .map(|Const(c)| self.constant_names.add_name(c))
-                .collect(),
-        );
-
-        let rel = self.declare_relation(name);
-
-        self.relations[rel].insert(tuple);
+        let constants = constants
+            .iter()
+            .map(|Const(c)| c.as_str())
+            .collect::<Vec<_>>();
+        self.insert_fact(name, &constants);
     }
 }
 
@@ -164,8 +422,10 @@ impl std::fmt::Display for DataSet {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for (rel, relation) in self.relations.iter().enumerate() {
             for tuple in relation.iter() {
-                write!(f, "{}(", &self.relation_names[rel])?;
-                let mut iter = tuple.0.iter().map(|c| &self.constant_names[*c]);
+                write!(f, "{}(", &self.names[RelationId::new(rel)])?;
+                let mut iter = tuple
+                    .iter()
+                    .map(|c| &self.names[ConstantId::new(*c as usize)]);
                 if let Some(first) = iter.next() {
                     write!(f, "{first}")?;
                 }
@@ -1,31 +1,70 @@
-use std::collections::BTreeSet as Set;
+use crate::{binding::Binding, name_pool::VariableId, DataSet};
 
-use crate::{binding::Binding, DataSet};
+#[cfg(feature = "serde")]
+mod de;
 
-/// An [`Answer`] is a set of pairs of strings, which correspond to a variable
+/// An [`Answer`] is a list of pairs of strings, which correspond to a variable
 /// name, which, when bound to a constant, produces an answer to some query.
-pub struct Answer(Set<(String, String)>);
+///
+/// The variables are in the order they first appear in the query.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Answer(Vec<(String, String)>);
 
 impl Answer {
-    pub(super) fn new(binding: &Binding, variables: &Binding, data: &DataSet) -> Answer {
+    pub(super) fn new<'n>(
+        binding: &Binding,
+        variables: &Binding,
+        data: &DataSet,
+        constant_name: impl Fn(usize) -> &'n str,
+    ) -> Answer {
         Answer(
             binding
                 .iter()
                 .map(|(v, c)| {
                     let var_name_index = variables[v];
-                    let var_name = &data.variable_names[var_name_index];
-                    let constant_name = &data.constant_names[c];
+                    let var_name = &data.names[VariableId::new(var_name_index)];
+                    let constant_name = constant_name(c);
                     (var_name.into(), constant_name.into())
                 })
                 .collect(),
         )
     }
+
+    /// The constant bound to a variable, if the variable is in the query.
+    pub fn get(&self, variable: &str) -> Option<&str> {
+        self.iter().find(|(v, _)| *v == variable).map(|(_, c)| c)
+    }
+
+    /// The `(variable, constant)` pairs, in query order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(v, c)| (v.as_str(), c.as_str()))
+    }
+
+    /// The number of variables in this answer.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Does this answer have no variables? This is the case for answers to
+    /// queries like `father(vader, luke)`.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl std::ops::Index<&str> for Answer {
+    type Output = str;
+
+    fn index(&self, variable: &str) -> &Self::Output {
+        self.get(variable)
+            .unwrap_or_else(|| panic!("no variable `{variable}` in answer {self}"))
+    }
 }
 
 impl std::fmt::Display for Answer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{{",)?;
-        let mut iter = self.0.iter();
+        let mut iter = self.iter();
         if let Some((v, c)) = iter.next() {
             write!(f, "{v} = {c}")?;
         }
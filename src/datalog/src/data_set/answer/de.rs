@@ -0,0 +1,168 @@
+//! Deserializing answers into Rust types with serde.
+//!
+//! Structs and maps are filled in by variable name, while tuples and
+//! sequences get the constants in query order. Constants are strings, but are
+//! parsed if something like a number or a `bool` is expected.
+
+use serde::{
+    de::{
+        value::{Error as DeError, MapDeserializer, SeqDeserializer},
+        DeserializeOwned, Deserializer, Error as _, IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any,
+};
+
+use crate::{Error, ErrorKind};
+
+use super::Answer;
+
+impl Answer {
+    /// Deserialize this answer into some type, like a struct with a field
+    /// for each variable, or a tuple with an element for each variable.
+    ///
+    /// ```
+    /// # use datalog::{BlockList, DataSet, Program, Query};
+    /// #[derive(serde::Deserialize)]
+    /// struct Parent {
+    ///     #[serde(rename = "P")]
+    ///     parent: String,
+    /// }
+    ///
+    /// let mut data = DataSet::default();
+    /// data.insert_fact("parent", &["padme", "luke"]);
+    ///
+    /// let query = Query::parse("parent(P, luke).", BlockList::OFF).unwrap();
+    /// let answer = data.query(&query).next().unwrap();
+    ///
+    /// assert_eq!(answer.deserialize::<Parent>().unwrap().parent, "padme");
+    /// assert_eq!(answer.deserialize::<(String,)>().unwrap().0, "padme");
+    /// ```
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        T::deserialize(AnswerDeserializer(self)).map_err(|e| {
+            Error::new(
+                ErrorKind::Deserialize,
+                format!("cannot deserialize answer {self}: {e}"),
+            )
+        })
+    }
+}
+
+struct AnswerDeserializer<'a>(&'a Answer);
+
+impl<'de, 'a> Deserializer<'de> for AnswerDeserializer<'a> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let pairs = self.0.iter().map(|(v, c)| (v, ConstantDeserializer(c)));
+        visitor.visit_map(MapDeserializer::new(pairs))
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let constants = self.0.iter().map(|(_, c)| ConstantDeserializer(c));
+        visitor.visit_seq(SeqDeserializer::new(constants))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+/// A single constant, which is a string but can be parsed as other things.
+struct ConstantDeserializer<'a>(&'a str);
+
+impl<'de, 'a> IntoDeserializer<'de, DeError> for ConstantDeserializer<'a> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+macro_rules! parse_constant {
+    ($($deserialize:ident => $visit:ident,)*) => {
+        $(
+            fn $deserialize<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match self.0.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => Err(DeError::custom(format!(
+                        "cannot parse constant `{}` for {}",
+                        self.0,
+                        stringify!($deserialize).trim_start_matches("deserialize_"),
+                    ))),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> Deserializer<'de> for ConstantDeserializer<'a> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_str(self.0)
+    }
+
+    parse_constant! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0
+            .into_deserializer()
+            .deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
@@ -0,0 +1,398 @@
+//! Lints for programs which parse fine, but probably don't do what was
+//! intended.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    ops::Range,
+};
+
+use crate::{
+    name_pool::{kind, RelationId},
+    parser::{
+        is_constant_name, Atom, Const, Fact, Input, Output, Program, Relation, Rule, Statement,
+        Term, Var,
+    },
+    DataSet, Diagnostics, Lint, LintKind,
+};
+
+use super::foreign::{ForeignPredicate, Mode};
+
+impl DataSet {
+    /// Look for problems in a program before it's added to this data set with
+    /// [`DataSet::program`]: variables which only appear once in a rule,
+    /// relations which nothing reads, rules which can never fire, facts which
+    /// are already known, and recursive rules which might never stop making
+    /// new constants.
+    ///
+    /// Lints point at the exact identifiers involved for programs parsed with
+    /// [`Program::parse_with_diagnostics`], and at whole statements otherwise.
+    pub fn check(&self, program: &Program, diagnostics: &mut Diagnostics) {
+        self.check_singletons(program, diagnostics);
+        self.check_unused(program, diagnostics);
+        self.check_never_fires(program, diagnostics);
+        self.check_duplicates(program, diagnostics);
+        self.check_unbounded(program, diagnostics);
+    }
+
+    fn check_singletons(&self, program: &Program, diagnostics: &mut Diagnostics) {
+        for (i, statement) in program.statements().iter().enumerate() {
+            let (head, body) = match statement {
+                Statement::Rule(Rule(head, body)) => (Some(head), body),
+                Statement::Constraint(body) => (None, body),
+                _ => continue,
+            };
+            let what = if head.is_some() { "rule" } else { "constraint" };
+
+            let spans = spans(program, i);
+            let mut seen: BTreeMap<&str, Vec<Range<usize>>> = BTreeMap::new();
+            for (name, span) in atom_names(head, body).zip(spans) {
+                if !is_constant_name(name) {
+                    seen.entry(name).or_default().push(span);
+                }
+            }
+
+            for (name, spans) in seen {
+                if let [span] = &spans[..] {
+                    if !name.starts_with('_') {
+                        diagnostics.lint(
+                            Lint::warning(
+                                LintKind::SingletonVariable,
+                                format!("`{name}` only appears once in this {what}"),
+                            )
+                            .with_labeled_span(span.start, span.len(), "only used here")
+                            .with_help(format!("if that's on purpose, call it `_{name}` instead")),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // A relation is unused if nothing reads it. Every relation is printed when
+    // there are no `.output` directives, so this only applies to programs
+    // with some.
+    fn check_unused(&self, program: &Program, diagnostics: &mut Diagnostics) {
+        let mut used = BTreeSet::new();
+        for (rel, _) in &self.outputs {
+            used.insert(&self.names[RelationId::new(*rel)]);
+        }
+        for rule in &self.rules {
+            used.extend(rule.body().map(|rel| &self.names[RelationId::new(rel)]));
+        }
+
+        let mut has_outputs = !self.outputs.is_empty();
+        for statement in program.statements() {
+            match statement {
+                Statement::Rule(Rule(_, body)) | Statement::Constraint(body) => {
+                    used.extend(body.iter().map(|Atom(Relation(name), _)| name.as_str()))
+                }
+                Statement::Output(Output(Relation(name), _)) => {
+                    has_outputs = true;
+                    used.insert(name);
+                }
+                _ => {}
+            }
+        }
+
+        if !has_outputs {
+            return;
+        }
+
+        let mut reported = BTreeSet::new();
+        for (i, statement) in program.statements().iter().enumerate() {
+            let name = match statement {
+                Statement::Fact(Fact(Relation(name), _)) => name,
+                Statement::Rule(Rule(Atom(Relation(name), _), _)) => name,
+                Statement::Input(Input(Relation(name), _)) => name,
+                _ => continue,
+            };
+
+            if !used.contains(name.as_str()) && reported.insert(name) {
+                let span = spans(program, i).next().unwrap();
+                diagnostics.lint(
+                    Lint::warning(LintKind::UnusedRelation, format!("`{name}` is never used"))
+                        .with_labeled_span(span.start, span.len(), "defined here")
+                        .with_help("no rule reads it, and no `.output` writes it"),
+                );
+            }
+        }
+    }
+
+    // Work out which relations could ever have facts, starting from the ones
+    // which do and following rules, then complain about the rules which need
+    // a relation that can't.
+    fn check_never_fires(&self, program: &Program, diagnostics: &mut Diagnostics) {
+        let mut possible = BTreeSet::new();
+        for (rel, tuples) in self.relations.iter().enumerate() {
+            if !tuples.is_empty() || self.foreign.contains_key(&rel) {
+                possible.insert(&self.names[RelationId::new(rel)]);
+            }
+        }
+
+        let mut rules: Vec<(&str, Vec<&str>)> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let head = &self.names[RelationId::new(rule.relation())];
+                let body = rule
+                    .body()
+                    .map(|rel| &self.names[RelationId::new(rel)])
+                    .collect();
+                (head, body)
+            })
+            .collect();
+
+        let mut program_rules = Vec::new();
+        for (i, statement) in program.statements().iter().enumerate() {
+            match statement {
+                Statement::Fact(Fact(Relation(name), _))
+                | Statement::Input(Input(Relation(name), _)) => {
+                    possible.insert(name);
+                }
+                Statement::Rule(Rule(Atom(Relation(head), _), body)) => {
+                    let body = body.iter().map(|Atom(Relation(name), _)| name.as_str());
+                    rules.push((head, body.collect()));
+                    program_rules.push(i);
+                }
+                _ => {}
+            }
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (head, body) in &rules {
+                if !possible.contains(head) && body.iter().all(|rel| possible.contains(rel)) {
+                    possible.insert(head);
+                    changed = true;
+                }
+            }
+        }
+
+        for i in program_rules {
+            let Statement::Rule(Rule(head, body)) = &program.statements()[i] else {
+                unreachable!()
+            };
+
+            // Complain about the first body atom which can never have facts,
+            // pointing at its relation name.
+            let spans: Vec<_> = spans(program, i)
+                .take(atom_names(Some(head), body).count())
+                .collect();
+            let mut position = 1 + head.1.len();
+            for Atom(Relation(name), terms) in body {
+                if !possible.contains(name.as_str()) {
+                    let span = &spans[position];
+                    diagnostics.lint(
+                        Lint::warning(LintKind::NeverFires, "this rule can never fire")
+                            .with_labeled_span(
+                                span.start,
+                                span.len(),
+                                format!("`{name}` never has any facts"),
+                            )
+                            .with_help(format!(
+                                "there are no facts for `{name}`, and no rules which could make some"
+                            )),
+                    );
+                    break;
+                }
+                position += 1 + terms.len();
+            }
+        }
+    }
+
+    fn check_duplicates(&self, program: &Program, diagnostics: &mut Diagnostics) {
+        let mut seen: BTreeMap<&Fact, Range<usize>> = BTreeMap::new();
+
+        for (i, statement) in program.statements().iter().enumerate() {
+            let Statement::Fact(fact) = statement else {
+                continue;
+            };
+
+            let Fact(Relation(name), constants) = fact;
+            let constants: Vec<&str> = constants.iter().map(|Const(c)| c.as_str()).collect();
+            let span = program.span(i);
+            let mut lint = Lint::note(
+                LintKind::DuplicateFact,
+                format!("`{name}({})` is already known", constants.join(", ")),
+            )
+            .with_labeled_span(span.start, span.len(), "this fact is a duplicate");
+
+            if let Some(first) = seen.get(fact) {
+                lint = lint.with_labeled_span(first.start, first.len(), "first stated here");
+                diagnostics.lint(lint);
+            } else if self.contains(name, &constants) {
+                diagnostics.lint(lint);
+            } else {
+                seen.insert(fact, span);
+            }
+        }
+    }
+
+    // Running the rules only finishes because there are only so many
+    // constants, but a foreign predicate can make up new ones. A rule which
+    // puts one of those into a relation it depends on can go round forever,
+    // so complain about those, unless the program has a `.bound`.
+    fn check_unbounded(&self, program: &Program, diagnostics: &mut Diagnostics) {
+        let bounded = self.bound.is_some()
+            || program
+                .statements()
+                .iter()
+                .any(|statement| matches!(statement, Statement::Bound(_)));
+        let creates = self
+            .foreign
+            .values()
+            .any(|predicate| predicate.modes().contains(&Mode::Free));
+        if bounded || !creates {
+            return;
+        }
+
+        let foreign = |name: &str| -> Option<&ForeignPredicate> {
+            let rel = self.names.get::<kind::Relation>(name)?;
+            self.foreign.get(&rel.index())
+        };
+
+        // The relations which each relation's facts are used to derive.
+        let mut derives: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for rule in &self.rules {
+            let head = &self.names[RelationId::new(rule.relation())];
+            for rel in rule.body() {
+                let rel = &self.names[RelationId::new(rel)];
+                derives.entry(rel).or_default().insert(head);
+            }
+        }
+        for statement in program.statements() {
+            if let Statement::Rule(Rule(Atom(Relation(head), _), body)) = statement {
+                for Atom(Relation(rel), _) in body {
+                    derives.entry(rel).or_default().insert(head);
+                }
+            }
+        }
+
+        for (i, statement) in program.statements().iter().enumerate() {
+            let Statement::Rule(Rule(head, body)) = statement else {
+                continue;
+            };
+            let Atom(Relation(name), head_terms) = head;
+
+            // A head variable which only a foreign predicate's free argument
+            // binds gets whatever constant the predicate makes up.
+            let from_facts: BTreeSet<&Term> = body
+                .iter()
+                .filter(|Atom(Relation(rel), _)| foreign(rel).is_none())
+                .flat_map(|Atom(_, terms)| terms)
+                .collect();
+            let mut made = None;
+            let mut position = 1 + head_terms.len();
+            for Atom(Relation(rel), terms) in body {
+                if let Some(predicate) = foreign(rel) {
+                    made = terms
+                        .iter()
+                        .zip(predicate.modes())
+                        .position(|(term, mode)| {
+                            !mode.is_bound()
+                                && matches!(term, Term::Var(_))
+                                && head_terms.contains(term)
+                                && !from_facts.contains(term)
+                        })
+                        .map(|j| (rel, &terms[j], position + 1 + j));
+                    if made.is_some() {
+                        break;
+                    }
+                }
+                position += 1 + terms.len();
+            }
+            let Some((rel, var, position)) = made else {
+                continue;
+            };
+
+            let read: BTreeSet<&str> = body
+                .iter()
+                .map(|Atom(Relation(rel), _)| rel.as_str())
+                .filter(|rel| foreign(rel).is_none())
+                .collect();
+            let Some(mut cycle) = path(&derives, name, &read) else {
+                continue;
+            };
+            cycle.push(name);
+
+            let spans: Vec<_> = spans(program, i)
+                .take(atom_names(Some(head), body).count())
+                .collect();
+            diagnostics.lint(
+                Lint::warning(
+                    LintKind::UnboundedRecursion,
+                    "this rule might keep making new constants forever",
+                )
+                .with_labeled_span(
+                    spans[position].start,
+                    spans[position].len(),
+                    format!("`{rel}` makes up a new constant for `{var}`"),
+                )
+                .with_labeled_span(
+                    spans[0].start,
+                    spans[0].len(),
+                    format!("which goes round {}", cycle.join(" -> ")),
+                )
+                .with_help(
+                    "if it does stop, say how many steps to take at most with \
+                     something like `.bound 1000.`",
+                ),
+            );
+        }
+    }
+}
+
+// The shortest way from `from` to one of `to`, following `derives`, with
+// both ends included.
+fn path<'a>(
+    derives: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+    from: &'a str,
+    to: &BTreeSet<&str>,
+) -> Option<Vec<&'a str>> {
+    let mut came_from = BTreeMap::from([(from, from)]);
+    let mut queue = VecDeque::from([from]);
+
+    while let Some(rel) = queue.pop_front() {
+        if to.contains(rel) {
+            let mut path = vec![rel];
+            while *path.last().unwrap() != from {
+                path.push(came_from[path.last().unwrap()]);
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        for next in derives.get(rel).into_iter().flatten() {
+            if !came_from.contains_key(next) {
+                came_from.insert(next, rel);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    None
+}
+
+// The relation and term names in a rule or constraint, in the order they're
+// written.
+fn atom_names<'a>(head: Option<&'a Atom>, body: &'a [Atom]) -> impl Iterator<Item = &'a str> {
+    head.into_iter()
+        .chain(body)
+        .flat_map(|Atom(Relation(rel), terms)| {
+            std::iter::once(rel.as_str()).chain(terms.iter().map(|term| match term {
+                Term::Const(Const(name)) | Term::Var(Var(name)) => name.as_str(),
+            }))
+        })
+}
+
+// The spans of the identifiers in a statement, or else the span of the whole
+// statement over and over if those aren't known.
+fn spans(program: &Program, statement: usize) -> impl Iterator<Item = Range<usize>> + '_ {
+    let identifiers = program.identifiers(statement);
+    let whole = program.span(statement);
+    identifiers
+        .iter()
+        .map(|i| i.span.clone())
+        .chain(std::iter::repeat(whole))
+}
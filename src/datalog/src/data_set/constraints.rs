@@ -0,0 +1,95 @@
+//! Checking that no facts match the constraints a data set has been given.
+
+use std::{ops::Range, sync::Arc};
+
+use crate::{
+    parser::{Atom, Query},
+    DataSet, Diagnostics, Error, ErrorKind,
+};
+
+/// The most bindings reported for each constraint. The rest are only counted.
+const SHOWN: usize = 10;
+
+/// A constraint like `:- parent(X, X).`, with the source it came from so
+/// it can be pointed at whenever it's broken, not only right after it was
+/// added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct Constraint {
+    pub(super) body: Vec<Atom>,
+    pub(super) name: Arc<str>,
+    pub(super) source: Arc<str>,
+    pub(super) span: Range<usize>,
+}
+
+impl DataSet {
+    /// Does this data set have any constraints to check?
+    pub fn has_constraints(&self) -> bool {
+        !self.constraints.is_empty()
+    }
+
+    pub(super) fn constraint(&mut self, constraint: Constraint) {
+        // Like rules, loading the same program again shouldn't mean
+        // checking (and reporting) the same constraint twice.
+        if self.constraints.iter().any(|c| c.body == constraint.body) {
+            return;
+        }
+
+        #[cfg(feature = "serde")]
+        if let Some(storage) = &mut self.storage {
+            storage.push(super::storage::Entry::Constraint(constraint.clone()));
+        }
+        self.constraints.push(constraint);
+    }
+
+    /// Report every binding which satisfies one of the constraints added to
+    /// this data set, like `:- parent(X, X).`, as an error pointing at the
+    /// constraint in the program it came from. The constraints are checked
+    /// against the facts as they are, so this should come after
+    /// [`DataSet::run`].
+    ///
+    /// ```
+    /// use datalog::{BlockList, DataSet, Diagnostics, ErrorKind, Program};
+    ///
+    /// let program = Program::parse(
+    ///     "parent(anakin, luke). parent(luke, luke). :- parent(X, X).",
+    ///     BlockList::OFF,
+    /// )
+    /// .unwrap();
+    ///
+    /// let mut data = DataSet::default();
+    /// data.program(&program).unwrap();
+    /// data.run();
+    ///
+    /// let mut diagnostics = Diagnostics::default();
+    /// data.check_constraints(&mut diagnostics);
+    /// let error = &diagnostics.errors()[0];
+    /// assert_eq!(error.kind(), ErrorKind::Constraint);
+    /// assert_eq!(error.to_string(), "error: constraint violated by {X = luke}");
+    /// ```
+    pub fn check_constraints(&mut self, diagnostics: &mut Diagnostics) {
+        for constraint in self.constraints.clone() {
+            let query = Query(constraint.body);
+            let mut answers = self.query(&query);
+            let shown: Vec<_> = answers.by_ref().take(SHOWN).collect();
+            let more = answers.count();
+
+            let span = constraint.span;
+            for (j, answer) in shown.iter().enumerate() {
+                let reason = if answer.is_empty() {
+                    "constraint violated".to_string()
+                } else {
+                    format!("constraint violated by {answer}")
+                };
+                let mut error = Error::new(ErrorKind::Constraint, reason)
+                    .with_labeled_span(span.start, span.len(), "this should never match")
+                    .with_source_code(&constraint.name, constraint.source.clone());
+                if more > 0 && j + 1 == shown.len() {
+                    let s = if more == 1 { "" } else { "s" };
+                    error = error.with_help(format!("and by {more} more binding{s}"));
+                }
+                diagnostics.error(error);
+            }
+        }
+    }
+}
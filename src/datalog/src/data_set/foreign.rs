@@ -0,0 +1,145 @@
+//! Predicates which are implemented in Rust instead of by facts and rules.
+
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    name_pool::kind,
+    parser::{Atom, Relation, Term, Var},
+    DataSet, Error, ErrorKind,
+};
+
+/// Does an argument to a foreign predicate need to be bound before it's
+/// called?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// The argument must be bound to a constant.
+    Bound,
+    /// The argument may be unbound, in which case the predicate produces the
+    /// values for it.
+    Free,
+}
+
+impl Mode {
+    pub(super) fn is_bound(self) -> bool {
+        self == Mode::Bound
+    }
+}
+
+type Callback = dyn Fn(&[Option<&str>]) -> Vec<Vec<String>> + Send + Sync;
+
+/// A predicate registered with [`DataSet::register_predicate`].
+pub(super) struct ForeignPredicate {
+    modes: Vec<Mode>,
+    callback: Box<Callback>,
+}
+
+impl ForeignPredicate {
+    pub(super) fn modes(&self) -> &[Mode] {
+        &self.modes
+    }
+
+    pub(super) fn call(&self, arguments: &[Option<&str>]) -> Vec<Vec<String>> {
+        (self.callback)(arguments)
+    }
+}
+
+impl fmt::Debug for ForeignPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ForeignPredicate")
+            .field("modes", &self.modes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DataSet {
+    /// Make `relation` a predicate which is worked out by calling some Rust
+    /// code, instead of by looking at its facts.
+    ///
+    /// There's one [`Mode`] for each argument. When the predicate is used in a
+    /// rule or query, `callback` is called with the constant for every bound
+    /// argument, and `None` for the rest. It returns every tuple which
+    /// satisfies the predicate, with one constant for each argument. Tuples
+    /// which don't match the bound arguments are ignored, as are tuples which
+    /// are the wrong length.
+    ///
+    /// Rules have to bind every [`Mode::Bound`] argument with an earlier
+    /// sub-goal, and can't derive facts for the predicate, or
+    /// [`DataSet::program`] rejects them. A query which leaves a bound
+    /// argument unbound calls the predicate once for every known constant.
+    ///
+    /// ```
+    /// # use datalog::{BlockList, DataSet, Mode, Query};
+    /// let mut data = DataSet::default();
+    /// data.register_predicate("shout", &[Mode::Bound, Mode::Free], |args| {
+    ///     let quiet = args[0].unwrap();
+    ///     vec![vec![quiet.to_string(), quiet.to_uppercase()]]
+    /// });
+    ///
+    /// let query = Query::parse("shout(hello, X).", BlockList::OFF).unwrap();
+    /// let answer = data.query(&query).next().unwrap();
+    /// assert_eq!(&answer["X"], "HELLO");
+    /// ```
+    pub fn register_predicate<F>(&mut self, relation: &str, modes: &[Mode], callback: F)
+    where
+        F: Fn(&[Option<&str>]) -> Vec<Vec<String>> + Send + Sync + 'static,
+    {
+        let rel = self.declare_relation(relation);
+        self.foreign.insert(
+            rel,
+            ForeignPredicate {
+                modes: modes.to_vec(),
+                callback: Box::new(callback),
+            },
+        );
+    }
+
+    /// Rules can't derive facts for a foreign predicate, since they'd never be
+    /// read, and have to bind its [`Mode::Bound`] arguments before calling it,
+    /// since otherwise it would be called with every combination of
+    /// constants.
+    pub(super) fn check_foreign(&self, head: &Atom, body: &[Atom]) -> Result<(), Error> {
+        let foreign = |Relation(name): &Relation| {
+            let rel = self.names.get::<kind::Relation>(name)?;
+            self.foreign.get(&rel.index())
+        };
+
+        let Atom(relation, _) = head;
+        if foreign(relation).is_some() {
+            return Err(Error::new(
+                ErrorKind::Foreign,
+                format!(
+                    "rules can't derive facts for `{}`, it's a foreign predicate",
+                    relation.0
+                ),
+            ));
+        }
+
+        let mut bound = BTreeSet::new();
+        for Atom(relation, terms) in body {
+            if let Some(predicate) = foreign(relation) {
+                for (term, mode) in terms.iter().zip(predicate.modes()) {
+                    match term {
+                        Term::Var(Var(v)) if mode.is_bound() && !bound.contains(v) => {
+                            return Err(Error::new(
+                                ErrorKind::Foreign,
+                                format!("`{}` needs `{v}` to be bound before it", relation.0),
+                            )
+                            .with_help(format!(
+                                "put a sub-goal which binds `{v}` before the call to `{}`",
+                                relation.0
+                            )));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            bound.extend(terms.iter().filter_map(|term| match term {
+                Term::Var(Var(v)) => Some(v),
+                Term::Const(_) => None,
+            }));
+        }
+
+        Ok(())
+    }
+}
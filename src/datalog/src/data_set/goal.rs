@@ -1,13 +1,15 @@
 use crate::{
     binding::Binding,
     data_set::Term,
+    name_pool::kind,
     parser::{Atom, Const, Relation, Term as TermSyntax, Var},
     DataSet,
 };
 
 use super::Tuple;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct Goal {
     pub(super) relation: usize,
     pub(super) terms: Vec<Term>,
@@ -22,9 +24,11 @@ impl Goal {
         let terms = terms
             .iter()
             .map(|t| match t {
-                TermSyntax::Const(Const(c)) => Term::Constant(data.constant_names.add_name(c)),
+                TermSyntax::Const(Const(c)) => {
+                    Term::Constant(data.names.add::<kind::Constant>(c).index())
+                }
                 TermSyntax::Var(Var(var)) => {
-                    let var_name_index = data.variable_names.add_name(var);
+                    let var_name_index = data.names.add::<kind::Variable>(var).index();
                     let v = variables.insert(var_name_index);
                     Term::Variable(v)
                 }
@@ -34,11 +38,6 @@ impl Goal {
         Goal { relation, terms }
     }
 
-    pub(super) fn is_satisfied_by(&self, binding: &Binding, data: &DataSet) -> bool {
-        let tuple = self.make_tuple(binding);
-        data.relations[self.relation].contains(&tuple)
-    }
-
     pub(super) fn make_tuple(&self, binding: &Binding) -> Tuple {
         let elements = self
             .terms
@@ -0,0 +1,82 @@
+//! Read-only views of what a data set knows.
+
+use crate::{
+    name_pool::{kind, ConstantId, RelationId},
+    DataSet,
+};
+
+use super::relation;
+
+/// A summary of one relation in a [`DataSet`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelationInfo<'d> {
+    /// The relation's name.
+    pub name: &'d str,
+
+    /// The number of elements in each tuple, or `None` if there are no facts
+    /// in the relation yet.
+    pub arity: Option<usize>,
+
+    /// The number of facts in the relation.
+    pub len: usize,
+}
+
+impl DataSet {
+    /// All the relations this data set knows about, including the ones which
+    /// only appear in rules and queries.
+    pub fn relations(&self) -> impl Iterator<Item = RelationInfo<'_>> {
+        self.relations
+            .iter()
+            .enumerate()
+            .map(|(rel, tuples)| RelationInfo {
+                name: &self.names[RelationId::new(rel)],
+                arity: tuples.arity(),
+                len: tuples.len(),
+            })
+    }
+
+    /// The facts in a relation, as lists of constants. This is `None` if
+    /// there's no relation with that name.
+    pub fn tuples(&self, relation: &str) -> Option<impl Iterator<Item = Vec<&str>>> {
+        let rel = self
+            .names
+            .get::<kind::Relation>(relation)
+            .map(RelationId::index)?;
+
+        Some(self.relations[rel].iter().map(|tuple| {
+            tuple
+                .iter()
+                .map(|c| &self.names[ConstantId::new(*c as usize)])
+                .collect()
+        }))
+    }
+
+    /// Is some fact, like `parent(padme, luke)`, known?
+    pub fn contains(&self, relation: &str, constants: &[&str]) -> bool {
+        let Some(rel) = self
+            .names
+            .get::<kind::Relation>(relation)
+            .map(RelationId::index)
+        else {
+            return false;
+        };
+
+        let tuple = constants
+            .iter()
+            .map(|c| {
+                let c = self.names.get::<kind::Constant>(c)?;
+                Some(relation::id(c.index()))
+            })
+            .collect::<Option<Vec<_>>>();
+
+        match tuple {
+            Some(tuple) => self.relations[rel].contains(&tuple),
+            None => false,
+        }
+    }
+
+    /// Has this constant appeared anywhere in the data set?
+    pub fn has_constant(&self, name: &str) -> bool {
+        self.names.get::<kind::Constant>(name).is_some()
+    }
+}
@@ -0,0 +1,208 @@
+//! Loading and saving relations as files.
+
+use std::{
+    fmt::Display,
+    fs,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    name_pool::{kind, ConstantId, RelationId},
+    DataSet, Error, ErrorKind,
+};
+
+/// The kinds of files relations can be loaded from or saved to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Csv,
+    Tsv,
+    /// An array of rows, each an array of strings. This is only for output.
+    Json,
+}
+
+impl Format {
+    /// Pick a format based on a file's extension, defaulting to CSV.
+    fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("tsv") => Format::Tsv,
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Format::Json,
+            _ => Format::Csv,
+        }
+    }
+
+    fn reader(self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All);
+
+        if self == Format::Tsv {
+            builder.delimiter(b'\t').quoting(false);
+        }
+
+        builder
+    }
+
+    fn writer(self) -> csv::WriterBuilder {
+        let mut builder = csv::WriterBuilder::new();
+
+        if self == Format::Tsv {
+            builder.delimiter(b'\t').quote_style(csv::QuoteStyle::Never);
+        }
+
+        builder
+    }
+}
+
+impl DataSet {
+    /// Load each row of a CSV or TSV file as a fact in `relation`.
+    ///
+    /// Files ending in `.tsv` are tab-separated, anything else is read as
+    /// CSV. There's no header row, and every cell is used as a constant as-is,
+    /// without going through a [`BlockList`][crate::BlockList].
+    ///
+    /// Every row has to have the same number of columns as the facts already
+    /// in `relation`, or as the first row if there aren't any. If any row is
+    /// wrong, nothing from the file is added.
+    pub fn load(&mut self, relation: &str, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let format = Format::from_path(path);
+
+        if format == Format::Json {
+            return Err(Error::new(
+                ErrorKind::UnsupportedFormat,
+                format!(
+                    "cannot read `{}`: only CSV and TSV files can be loaded",
+                    path.display()
+                ),
+            ));
+        }
+
+        let input = fs::read_to_string(path).map_err(|e| cannot("read", path, e))?;
+
+        let rel = self.declare_relation(relation);
+        let mut arity = self.relations[rel].arity();
+
+        let mut rows = Vec::new();
+        let mut errors = Vec::new();
+
+        for record in format.reader().from_reader(input.as_bytes()).into_records() {
+            let record = record.map_err(|e| {
+                Error::new(
+                    ErrorKind::Io,
+                    format!("cannot read `{}`: {e}", path.display()),
+                )
+            })?;
+
+            let expected = *arity.get_or_insert(record.len());
+
+            if record.len() != expected {
+                let start = record.position().map_or(0, |p| p.byte() as usize);
+                let len = input[start..]
+                    .find(['\r', '\n'])
+                    .unwrap_or(input.len() - start);
+
+                errors.push(
+                    Error::new(
+                        ErrorKind::Arity,
+                        format!("wrong number of columns for `{relation}`"),
+                    )
+                    .with_labeled_span(
+                        start,
+                        len,
+                        format!("expected {expected} columns, found {}", record.len()),
+                    )
+                    .with_source_code(path.display().to_string(), input.as_str()),
+                );
+                continue;
+            }
+
+            rows.push(record);
+        }
+
+        if errors.is_empty() {
+            for row in rows {
+                self.insert_fact(relation, &row.iter().collect::<Vec<_>>());
+            }
+            Ok(())
+        } else {
+            Err(Error::from(errors))
+        }
+    }
+
+    /// Write every fact in `relation` to a file, one row per fact.
+    ///
+    /// The format is picked from the file's extension like [`DataSet::load`],
+    /// except that `.json` files are written as an array of rows, where each
+    /// row is an array of strings.
+    pub fn save(&self, relation: &str, path: impl AsRef<Path>) -> Result<(), Error> {
+        let rel = self
+            .names
+            .get::<kind::Relation>(relation)
+            .map(RelationId::index)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::UnknownRelation,
+                    format!("there is no relation named `{relation}`"),
+                )
+            })?;
+
+        self.write_relation(rel, path.as_ref())
+    }
+
+    /// Write out every relation named in an `.output` directive.
+    ///
+    /// This should be called after [`DataSet::run`], otherwise the files will
+    /// only have the facts which are known so far.
+    pub fn write_outputs(&self) -> Result<(), Error> {
+        for (rel, path) in &self.outputs {
+            self.write_relation(*rel, path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Are there any `.output` directives to write?
+    pub fn has_outputs(&self) -> bool {
+        !self.outputs.is_empty()
+    }
+
+    fn write_relation(&self, rel: usize, path: &Path) -> Result<(), Error> {
+        let rows = self.relations[rel].iter().map(|tuple| {
+            tuple
+                .iter()
+                .map(|c| &self.names[ConstantId::new(*c as usize)])
+        });
+
+        let file = fs::File::create(path).map_err(|e| cannot("write", path, e))?;
+        let mut file = BufWriter::new(file);
+
+        match Format::from_path(path) {
+            Format::Json => {
+                let rows = rows.map(Iterator::collect).collect::<Vec<Vec<_>>>();
+                serde_json::to_writer(&mut file, &rows).map_err(|e| cannot("write", path, e))?;
+                writeln!(file).map_err(|e| cannot("write", path, e))?;
+            }
+            format => {
+                let mut writer = format.writer().from_writer(&mut file);
+                for row in rows {
+                    writer
+                        .write_record(row)
+                        .map_err(|e| cannot("write", path, e))?;
+                }
+                writer.flush().map_err(|e| cannot("write", path, e))?;
+            }
+        }
+
+        file.flush().map_err(|e| cannot("write", path, e))
+    }
+}
+
+fn cannot(verb: &str, path: &Path, error: impl Display) -> Error {
+    Error::new(
+        ErrorKind::Io,
+        format!("cannot {verb} `{}`: {error}", path.display()),
+    )
+}
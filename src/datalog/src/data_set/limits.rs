@@ -0,0 +1,249 @@
+//! Stopping a run of the rules which is taking too long or finding too much.
+//!
+//! Limits are checked between steps, and every so often while rules are being
+//! matched, so a step which would go on for hours is stopped part way through
+//! too. Whatever the run found is thrown away, leaving the data set as it was
+//! before, since half of a fixpoint isn't much use to anybody. The facts it
+//! derives are written down as it goes, so they can be taken out again.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{name_pool::kind, DataSet, Error, ErrorKind};
+
+use super::Set;
+
+/// How far [`DataSet::run_with_limits`] can go before it gives up.
+///
+/// ```
+/// use datalog::{BlockList, DataSet, ErrorKind, Limits, Program};
+///
+/// let program = Program::parse(
+///     "edge(a, b). edge(b, c). edge(c, d). path(X, Y) :- edge(X, Y). \
+///      path(X, Z) :- path(X, Y), edge(Y, Z).",
+///     BlockList::OFF,
+/// )
+/// .unwrap();
+///
+/// let mut data = DataSet::default();
+/// data.program(&program).unwrap();
+///
+/// let limits = Limits {
+///     facts: Some(4),
+///     ..Limits::default()
+/// };
+/// let error = data.run_with_limits(&limits).unwrap_err();
+/// assert_eq!(error.kind(), ErrorKind::Limit);
+/// assert!(!data.contains("path", &["a", "b"]));
+///
+/// data.run();
+/// assert!(data.contains("path", &["a", "d"]));
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Limits {
+    /// How long the rules can be applied for.
+    pub time: Option<Duration>,
+    /// How many new facts the rules can derive.
+    pub facts: Option<usize>,
+    /// How many times each rule can be applied to all the facts.
+    pub iterations: Option<usize>,
+    /// Stops the run as soon as it's cancelled.
+    pub cancel: Option<Cancel>,
+}
+
+/// A flag which stops a run from somewhere else, like another thread or a
+/// Ctrl-C handler. Clones share the same flag.
+#[derive(Debug, Default, Clone)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn new() -> Self {
+        Cancel::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clear the flag, so the token can stop another run.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Why a run stopped before it found every fact.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Reason {
+    Time,
+    Facts,
+    Iterations,
+    Cancelled,
+}
+
+/// What a step checks while it applies the rules.
+pub(super) struct Stop<'l> {
+    deadline: Option<Instant>,
+    cancel: Option<&'l Cancel>,
+    /// How many more facts the rules can find.
+    facts: Option<usize>,
+    /// Set once some rule has found more than that.
+    too_many: AtomicBool,
+}
+
+impl<'l> Stop<'l> {
+    /// Stop when `limits` says to, or after `facts` new facts.
+    pub(super) fn new(limits: &'l Limits, deadline: Option<Instant>, facts: Option<usize>) -> Self {
+        Stop {
+            deadline,
+            cancel: limits.cancel.as_ref(),
+            facts,
+            too_many: AtomicBool::new(false),
+        }
+    }
+
+    /// Why the step should stop, if it should.
+    pub(super) fn reason(&self) -> Option<Reason> {
+        if self.cancel.is_some_and(Cancel::is_cancelled) {
+            Some(Reason::Cancelled)
+        } else if self.too_many.load(Ordering::Relaxed) {
+            Some(Reason::Facts)
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Some(Reason::Time)
+        } else {
+            None
+        }
+    }
+
+    /// Note that one rule has found this many new facts, and say whether
+    /// that's too many.
+    pub(super) fn found(&self, facts: usize) -> bool {
+        let too_many = self.facts.is_some_and(|max| facts > max);
+        if too_many {
+            self.too_many.store(true, Ordering::Relaxed);
+        }
+        too_many
+    }
+}
+
+impl DataSet {
+    /// Like [`DataSet::run`], but give up with an error if it goes over any
+    /// of the `limits`. Then the data set is left as it was before, which
+    /// means keeping a list of the new facts while the rules are applied.
+    pub fn run_with_limits(&mut self, limits: &Limits) -> Result<(), Error> {
+        let bounded = limits.time.is_some()
+            || limits.facts.is_some()
+            || limits.iterations.is_some()
+            || limits.cancel.is_some();
+        if !bounded || !self.is_dirty() {
+            self.run();
+            return Ok(());
+        }
+
+        let constants = self.constants_count();
+        let last_len = self.last_len;
+        // The steps it took are forgotten too, so the stats only count facts
+        // which were kept.
+        let stats = self.stats.clone();
+
+        self.undo = Some(Vec::new());
+        let result = self.run_until(limits, limits.time.map(|time| Instant::now() + time));
+        let undo = self.undo.take().unwrap_or_default();
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(reason) => {
+                let mut derived: BTreeMap<usize, Set<Box<[u32]>>> = BTreeMap::new();
+                for (rel, tuple) in undo {
+                    derived.entry(rel).or_default().insert(tuple);
+                }
+                for (rel, tuples) in derived {
+                    self.relations[rel].remove(&tuples);
+                }
+                self.names.truncate::<kind::Constant>(constants);
+                self.last_len = last_len;
+                self.stats = stats;
+                Err(reason.error(limits))
+            }
+        }
+    }
+
+    pub(super) fn run_until(
+        &mut self,
+        limits: &Limits,
+        deadline: Option<Instant>,
+    ) -> Result<(), Reason> {
+        let start = self.len();
+
+        for iteration in 0.. {
+            if !self.is_dirty() {
+                break;
+            }
+            if self.bound.is_some_and(|max| iteration >= max) {
+                // The program asked to stop here, so the run is finished,
+                // rather than cut short.
+                self.last_len = self.len();
+                break;
+            }
+            if limits.iterations.is_some_and(|max| iteration >= max) {
+                return Err(Reason::Iterations);
+            }
+
+            let facts = limits.facts.map(|max| max - (self.len() - start));
+            let stop = Stop::new(limits, deadline, facts);
+
+            self.last_len = self.len();
+            self.trace_step(iteration + 1);
+            self.step(&stop)?;
+
+            if limits.facts.is_some_and(|max| self.len() - start > max) {
+                return Err(Reason::Facts);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Reason {
+    pub(super) fn error(self, limits: &Limits) -> Error {
+        let (kind, reason) = match self {
+            Reason::Time => (
+                ErrorKind::Limit,
+                format!(
+                    "the rules ran for longer than {}s",
+                    limits.time.unwrap_or_default().as_secs_f64()
+                ),
+            ),
+            Reason::Facts => (
+                ErrorKind::Limit,
+                format!(
+                    "the rules derived more than {} new facts",
+                    limits.facts.unwrap_or_default()
+                ),
+            ),
+            Reason::Iterations => (
+                ErrorKind::Limit,
+                format!(
+                    "the rules were applied {} times without finding every fact",
+                    limits.iterations.unwrap_or_default()
+                ),
+            ),
+            Reason::Cancelled => (ErrorKind::Cancelled, "cancelled".into()),
+        };
+
+        Error::new(kind, reason).with_help("none of the facts they derived were kept")
+    }
+}
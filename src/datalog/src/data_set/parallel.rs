@@ -0,0 +1,115 @@
+//! Working out new facts on more than one thread.
+
+use std::{
+    ops::Range,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rayon::prelude::*;
+
+use crate::DataSet;
+
+use super::{limits::Stop, rule::Derived};
+
+/// Rules whose first sub-goal has more tuples than this are split into parts
+/// which can be worked on at once.
+const PART_SIZE: usize = 1024;
+
+/// Where [`DataSet::run`] does its work.
+#[derive(Debug, Default, Clone)]
+pub(super) enum Threads {
+    /// Rayon's global pool, with a thread for each core.
+    #[default]
+    Global,
+    /// Just the calling thread.
+    One,
+    /// A pool of its own.
+    Pool(Arc<rayon::ThreadPool>),
+}
+
+/// The new facts one part of one rule found, for some relation.
+pub(super) struct Found {
+    pub(super) rule: usize,
+    pub(super) relation: usize,
+    pub(super) derived: Derived,
+    pub(super) time: Duration,
+}
+
+impl DataSet {
+    /// Set how many threads [`DataSet::run`] uses to apply rules. With `0`,
+    /// which is the default, there's one for each core. With `1`, rules are
+    /// applied on the calling thread.
+    ///
+    /// The results are the same whatever this is, down to the order facts
+    /// are printed in.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = match threads {
+            0 => Threads::Global,
+            1 => Threads::One,
+            n => match rayon::ThreadPoolBuilder::new().num_threads(n).build() {
+                Ok(pool) => Threads::Pool(Arc::new(pool)),
+                Err(_) => Threads::One,
+            },
+        };
+    }
+
+    /// Apply every rule to the facts known so far, without adding anything.
+    ///
+    /// Each rule, and each part of a rule with a big first sub-goal, can be
+    /// worked on at once. What they find comes back in rule order, and parts
+    /// in order within each rule, so that merging it is deterministic.
+    ///
+    /// Every rule stops early once `stop` says so, and then what they found
+    /// is incomplete.
+    pub(super) fn apply_rules(&self, stop: &Stop) -> Vec<Found> {
+        let work = |(i, part): (usize, Option<Range<usize>>)| {
+            let start = Instant::now();
+            let rule = &self.rules[i];
+            let derived = rule.step(self, part, stop, self.traces(rule));
+            Found {
+                rule: i,
+                relation: rule.relation(),
+                derived,
+                time: start.elapsed(),
+            }
+        };
+
+        match &self.threads {
+            Threads::One => self.jobs(1).into_iter().map(work).collect(),
+            Threads::Global => {
+                let jobs = self.jobs(rayon::current_num_threads());
+                jobs.into_par_iter().map(work).collect()
+            }
+            Threads::Pool(pool) => pool.install(|| {
+                let jobs = self.jobs(pool.current_num_threads());
+                jobs.into_par_iter().map(work).collect()
+            }),
+        }
+    }
+
+    /// Each rule, split into as many parts as are worth working on at once.
+    fn jobs(&self, threads: usize) -> Vec<(usize, Option<Range<usize>>)> {
+        let mut jobs = Vec::new();
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            let len = match rule.first_relation(self) {
+                Some(relation) => self.relations[relation].len(),
+                None => 0,
+            };
+
+            let parts = len.div_ceil(PART_SIZE).min(threads);
+            if parts <= 1 {
+                jobs.push((i, None));
+                continue;
+            }
+
+            let size = len.div_ceil(parts);
+            for start in (0..len).step_by(size) {
+                jobs.push((i, Some(start..(start + size).min(len))));
+            }
+        }
+
+        jobs
+    }
+}
@@ -1,6 +1,6 @@
-use crate::{binding::Binding, counter::Counter, data_set::goal::Goal, parser::Atom, DataSet};
+use crate::{binding::Binding, data_set::goal::Goal, parser::Atom, DataSet, Limits};
 
-use super::Set;
+use super::{solve::Solutions, tabled::TabledAnswers, Answer};
 
 #[derive(Debug)]
 pub(super) struct Query {
@@ -23,31 +23,42 @@ impl Query {
         }
     }
 
-    pub(super) fn bindings<'d>(&'d self, data: &'d DataSet) -> Set<Binding> {
-        let mut set = Set::default();
-
-        for var_binding in
-            Counter::new(self.variables.len(), data.constants_count()).map(Binding::from)
-        {
-            if satisfies_all(&var_binding, &self.sub_goals, data) {
-                set.insert(var_binding);
-            }
+    /// The answers to this query, found lazily.
+    pub(super) fn answers(self, data: &DataSet) -> Answers<'_> {
+        Answers {
+            solutions: Solutions::new(self.sub_goals, self.variables.len(), data),
+            variables: self.variables,
         }
-
-        set
     }
 
-    pub(super) fn variables(&self) -> &Binding {
-        &self.variables
+    /// The answers to this query, worked out top-down with tabling, giving
+    /// up if that goes over some `limits`.
+    pub(super) fn tabled_answers<'d>(
+        self,
+        data: &'d DataSet,
+        limits: Option<&'d Limits>,
+    ) -> TabledAnswers<'d> {
+        TabledAnswers::new(self.sub_goals, self.variables, data, limits)
     }
 }
 
-pub(crate) fn satisfies_all(vars: &Binding, goals: &[Goal], data: &DataSet) -> bool {
-    for goal in goals {
-        if !goal.is_satisfied_by(vars, data) {
-            return false;
-        }
-    }
+/// The iterator returned by [`DataSet::query`].
+pub(super) struct Answers<'d> {
+    variables: Binding,
+    solutions: Solutions<'d>,
+}
 
-    true
+impl<'d> Iterator for Answers<'d> {
+    type Item = Answer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let binding = self.solutions.next()?;
+        let solutions = &self.solutions;
+        Some(Answer::new(
+            &binding,
+            &self.variables,
+            solutions.data(),
+            |c| solutions.constant_name(c),
+        ))
+    }
 }
@@ -0,0 +1,253 @@
+//! How the facts in a relation are stored.
+//!
+//! A tuple is usually only a few constant ids, so storing each one as its own
+//! `Vec<usize>` in a `BTreeSet` spends most of the memory on pointers, heap
+//! allocations and tree nodes rather than on the ids. Instead, the tuples of
+//! each arity are laid end to end in one sorted buffer of 32-bit ids, and the
+//! tuple at some index is found by multiplying by the arity.
+//!
+//! Inserting into the middle of a sorted buffer means moving everything after
+//! it, so new tuples go into a small set of their own first. Once that's big
+//! enough compared to the buffer, the two are merged in one pass, which keeps
+//! the cost of an insert down to a handful of copies on average.
+
+use std::{
+    cmp::Ordering,
+    collections::{btree_set, BTreeMap, BTreeSet},
+    iter::Peekable,
+};
+
+/// The set is merged into the buffer once it has more than this many tuples,
+/// plus an eighth of the number in the buffer.
+const PENDING: usize = 64;
+
+/// Turn a constant's id into the 32-bit form tuples are stored in.
+pub(super) fn id(c: usize) -> u32 {
+    u32::try_from(c).expect("there are too many constants to store")
+}
+
+/// The facts in one relation, as sets of constant ids.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct Relation {
+    /// The tuples of each arity. There's almost always just one.
+    arities: BTreeMap<usize, Rows>,
+}
+
+impl Relation {
+    pub(super) fn len(&self) -> usize {
+        self.arities.values().map(Rows::len).sum()
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of constants in each of this relation's tuples, or `None`
+    /// if it doesn't have any yet.
+    pub(super) fn arity(&self) -> Option<usize> {
+        self.arities.keys().next().copied()
+    }
+
+    pub(super) fn contains(&self, tuple: &[u32]) -> bool {
+        self.arities
+            .get(&tuple.len())
+            .is_some_and(|rows| rows.contains(tuple))
+    }
+
+    /// Add a tuple, returning whether it's new.
+    pub(super) fn insert(&mut self, tuple: &[u32]) -> bool {
+        self.arities
+            .entry(tuple.len())
+            .or_insert_with(|| Rows::new(tuple.len()))
+            .insert(tuple)
+    }
+
+    /// Take some tuples out again.
+    pub(super) fn remove(&mut self, tuples: &BTreeSet<Box<[u32]>>) {
+        for rows in self.arities.values_mut() {
+            rows.remove(tuples);
+        }
+        self.arities.retain(|_, rows| rows.len() > 0);
+    }
+
+    /// Every tuple, shortest first, and in order of their ids within each
+    /// arity.
+    pub(super) fn iter(&self) -> impl Iterator<Item = &[u32]> {
+        self.arities.values().flat_map(Rows::iter)
+    }
+}
+
+/// The tuples of one arity.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Rows {
+    arity: usize,
+    /// The number of tuples in `ids`, which can't be worked out from its
+    /// length when the arity is 0.
+    merged: usize,
+    /// Sorted tuples, one after another.
+    ids: Vec<u32>,
+    /// Tuples which aren't in `ids` yet.
+    pending: BTreeSet<Box<[u32]>>,
+}
+
+impl Rows {
+    fn new(arity: usize) -> Self {
+        Rows {
+            arity,
+            merged: 0,
+            ids: Vec::new(),
+            pending: BTreeSet::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.merged + self.pending.len()
+    }
+
+    fn row(&self, i: usize) -> &[u32] {
+        &self.ids[i * self.arity..(i + 1) * self.arity]
+    }
+
+    fn search(&self, tuple: &[u32]) -> Result<usize, usize> {
+        let (mut low, mut high) = (0, self.merged);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.row(middle).cmp(tuple) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return Ok(middle),
+            }
+        }
+        Err(low)
+    }
+
+    fn contains(&self, tuple: &[u32]) -> bool {
+        self.search(tuple).is_ok() || self.pending.contains(tuple)
+    }
+
+    fn insert(&mut self, tuple: &[u32]) -> bool {
+        if self.search(tuple).is_ok() || !self.pending.insert(tuple.into()) {
+            return false;
+        }
+
+        if self.pending.len() > PENDING + self.merged / 8 {
+            self.merge();
+        }
+        true
+    }
+
+    // The buffer is squeezed up in place, so this doesn't need room for a
+    // second copy of it.
+    fn remove(&mut self, tuples: &BTreeSet<Box<[u32]>>) {
+        self.pending.retain(|tuple| !tuples.contains(tuple));
+
+        let arity = self.arity;
+        let mut kept = 0;
+        for i in 0..self.merged {
+            let row = i * arity..(i + 1) * arity;
+            if !tuples.contains(&self.ids[row.clone()]) {
+                self.ids.copy_within(row, kept * arity);
+                kept += 1;
+            }
+        }
+        self.ids.truncate(kept * arity);
+        self.merged = kept;
+    }
+
+    fn merge(&mut self) {
+        let mut ids = Vec::with_capacity(self.len() * self.arity);
+        for tuple in self.iter() {
+            ids.extend_from_slice(tuple);
+        }
+
+        self.ids = ids;
+        self.merged = self.len();
+        self.pending.clear();
+    }
+
+    fn iter(&self) -> Iter<'_> {
+        Iter {
+            rows: self,
+            next: 0,
+            pending: self.pending.iter().peekable(),
+        }
+    }
+}
+
+/// The tuples in a [`Rows`], merging the buffer and the pending set in order.
+struct Iter<'r> {
+    rows: &'r Rows,
+    next: usize,
+    pending: Peekable<btree_set::Iter<'r, Box<[u32]>>>,
+}
+
+impl<'r> Iterator for Iter<'r> {
+    type Item = &'r [u32];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let merged = (self.next < self.rows.merged).then(|| self.rows.row(self.next));
+
+        match (merged, self.pending.peek()) {
+            (Some(row), Some(pending)) if &pending[..] < row => self.pending.next().map(|t| &**t),
+            (Some(row), _) => {
+                self.next += 1;
+                Some(row)
+            }
+            (None, _) => self.pending.next().map(|t| &**t),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.rows.merged - self.next + self.pending.len();
+        (len, Some(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserts_stay_sorted_and_unique() {
+        let mut relation = Relation::default();
+        let mut expected = BTreeSet::new();
+
+        // Enough to merge the pending tuples into the buffer a few times.
+        for i in 0..2000u32 {
+            let tuple = [i * 7919 % 1000, i % 3];
+            assert_eq!(relation.insert(&tuple), expected.insert(tuple));
+        }
+
+        assert_eq!(relation.len(), expected.len());
+        assert!(relation.iter().eq(expected.iter().map(|t| &t[..])));
+        assert!(relation.contains(&[5, 2]));
+        assert!(!relation.contains(&[5, 7]));
+
+        // Some of the tuples to take out have been merged, and some haven't.
+        let removed: BTreeSet<Box<[u32]>> = expected
+            .iter()
+            .filter(|tuple| tuple[0] % 3 == 0)
+            .map(|tuple| tuple[..].into())
+            .collect();
+        relation.remove(&removed);
+        expected.retain(|tuple| tuple[0] % 3 != 0);
+        assert!(relation.iter().eq(expected.iter().map(|t| &t[..])));
+        assert!(!relation.contains(&[5]));
+        assert_eq!(relation.arity(), Some(2));
+    }
+
+    #[test]
+    fn mixed_arities() {
+        let mut relation = Relation::default();
+        assert!(relation.insert(&[1, 2]));
+        assert!(relation.insert(&[]));
+        assert!(!relation.insert(&[]));
+        assert!(relation.insert(&[3]));
+
+        let tuples: Vec<&[u32]> = relation.iter().collect();
+        assert_eq!(tuples, [&[][..], &[3], &[1, 2]]);
+        assert_eq!(relation.len(), 3);
+    }
+}
@@ -1,8 +1,21 @@
-use crate::{binding::Binding, counter::Counter, data_set::goal::Goal, parser::Atom, DataSet};
+use std::{collections::BTreeMap, ops::Range};
 
-use super::{query::satisfies_all, Set, Tuple};
+use crate::{binding::Binding, data_set::goal::Goal, name_pool::Interner, parser::Atom, DataSet};
 
-#[derive(Debug)]
+use super::{limits::Stop, solve::Solutions, Set, Tuple};
+
+/// What applying a rule found.
+pub(super) struct Derived {
+    pub(super) facts: Set<Tuple>,
+    pub(super) new_constants: Interner,
+    /// How many times the rule's body was satisfied.
+    pub(super) considered: usize,
+    /// The binding each fact first came from, if they're being traced.
+    pub(super) bindings: BTreeMap<Tuple, Binding>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(super) struct Rule {
     goal: Goal,
     sub_goals: Vec<Goal>,
@@ -27,28 +40,94 @@ impl Rule {
         }
     }
 
-    pub(super) fn step<'d>(&'d self, data: &'d DataSet) -> Set<Tuple> {
+    /// Find every tuple this rule produces from the facts in `data` which
+    /// isn't already one of them.
+    ///
+    /// Foreign predicates might produce constants which aren't in `data` yet.
+    /// Those are returned too, and tuples refer to them by their index in the
+    /// returned [`Interner`], plus the number of constants in `data`.
+    ///
+    /// With a `part`, only those of the first sub-goal's tuples are tried.
+    /// The search ends early if `stop` says so. With `trace`, the binding
+    /// behind each tuple is kept as well.
+    pub(super) fn step(
+        &self,
+        data: &DataSet,
+        part: Option<Range<usize>>,
+        stop: &Stop,
+        trace: bool,
+    ) -> Derived {
+        let known = &data.relations[self.relation()];
         let mut set = Set::default();
+        let mut bindings = BTreeMap::new();
+        let mut considered = 0;
 
-        for var_binding in
-            Counter::new(self.variables.len(), data.constants_count()).map(Binding::from)
-        {
-            if satisfies_all(&var_binding, &self.sub_goals, data) {
-                set.insert(self.goal.make_tuple(&var_binding));
+        let mut solutions = Solutions::new(&self.sub_goals[..], self.variables.len(), data);
+        if let Some(part) = part {
+            solutions = solutions.part(part);
+        }
+        solutions = solutions.stop(stop);
+        for var_binding in &mut solutions {
+            considered += 1;
+            let tuple = self.goal.make_tuple(&var_binding);
+            if !known.contains(&tuple.0) {
+                if trace {
+                    bindings.entry(tuple.clone()).or_insert(var_binding);
+                }
+                set.insert(tuple);
+                if stop.found(set.len()) {
+                    break;
+                }
             }
         }
 
-        set
+        Derived {
+            facts: set,
+            new_constants: solutions.into_new_constants(),
+            considered,
+            bindings,
+        }
     }
 
     pub(super) fn relation(&self) -> usize {
         self.goal.relation
     }
+
+    pub(super) fn head(&self) -> &Goal {
+        &self.goal
+    }
+
+    /// The name of each of this rule's variables, as variable ids.
+    pub(super) fn variables(&self) -> &Binding {
+        &self.variables
+    }
+
+    pub(super) fn sub_goals(&self) -> &[Goal] {
+        &self.sub_goals
+    }
+
+    /// The number of distinct variables in this rule.
+    pub(super) fn variable_count(&self) -> usize {
+        self.variables.len()
+    }
+
+    /// The relation whose tuples the first sub-goal is matched against, if
+    /// it's not a foreign predicate. Searches for this rule can be split up by
+    /// those tuples.
+    pub(super) fn first_relation(&self, data: &DataSet) -> Option<usize> {
+        let first = self.sub_goals.first()?.relation;
+        (!data.foreign.contains_key(&first)).then_some(first)
+    }
+
+    /// The relations in this rule's body.
+    pub(super) fn body(&self) -> impl Iterator<Item = usize> + '_ {
+        self.sub_goals.iter().map(|goal| goal.relation)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{BlockList, Program};
+    use crate::{BlockList, Limits, Program};
 
     use super::*;
 
@@ -57,7 +136,7 @@ mod tests {
         let input = " p(a). p(b). ";
         let program = Program::parse(input, BlockList::OFF).unwrap();
         let mut data = DataSet::default();
-        data.program(&program);
+        data.program(&program).unwrap();
 
         let crate::parser::Rule(head, clauses) =
             crate::parser::Rule::parse(" q(X) :- p(X). ", BlockList::OFF).unwrap();
@@ -71,7 +150,7 @@ mod tests {
         let input = " p(a). p(b). q(c) ";
         let program = Program::parse(input, BlockList::OFF).unwrap();
         let mut data = DataSet::default();
-        data.program(&program);
+        data.program(&program).unwrap();
 
         let crate::parser::Rule(head, clauses) =
             crate::parser::Rule::parse(" q(X) :- p(X). ", BlockList::OFF).unwrap();
@@ -79,7 +158,13 @@ mod tests {
         let rule = Rule::new(&head, &clauses, &mut data);
 
         assert_eq!(
-            rule.step(&data),
+            rule.step(
+                &data,
+                None,
+                &Stop::new(&Limits::default(), None, None),
+                false
+            )
+            .facts,
             Set::from_iter(vec![vec![0].into(), vec![1].into()])
         );
     }
@@ -0,0 +1,57 @@
+//! Saving a whole data set to disk and loading it back, so a fixpoint doesn't
+//! have to be worked out again from source.
+//!
+//! A snapshot is a short header followed by the data set encoded with
+//! [`bincode`]. The header has some magic bytes so we don't try to load
+//! other files, and a version number which is bumped whenever the layout of
+//! [`DataSet`] changes, since old snapshots can't be read after that.
+
+use std::io::{Read, Write};
+
+use crate::{DataSet, Error, ErrorKind};
+
+const MAGIC: &[u8; 4] = b"DXDL";
+
+/// The version of the snapshot format.
+const VERSION: u32 = 6;
+
+impl DataSet {
+    /// Write a snapshot of everything in this data set, including its rules.
+    ///
+    /// Foreign predicates can't be saved, and have to be registered again
+    /// after the snapshot is loaded.
+    pub fn write_snapshot(&self, mut writer: impl Write) -> Result<(), Error> {
+        writer
+            .write_all(MAGIC)
+            .and_then(|_| writer.write_all(&VERSION.to_le_bytes()))
+            .map_err(|e| Error::new(ErrorKind::Snapshot, format!("cannot write snapshot: {e}")))?;
+
+        bincode::serialize_into(writer, self)
+            .map_err(|e| Error::new(ErrorKind::Snapshot, format!("cannot write snapshot: {e}")))
+    }
+
+    /// Read a data set from a snapshot made by [`DataSet::write_snapshot`].
+    pub fn read_snapshot(mut reader: impl Read) -> Result<DataSet, Error> {
+        let mut header = [0; 8];
+        reader
+            .read_exact(&mut header)
+            .map_err(|e| Error::new(ErrorKind::Snapshot, format!("cannot read snapshot: {e}")))?;
+
+        if &header[..4] != MAGIC {
+            return Err(Error::new(
+                ErrorKind::Snapshot,
+                "cannot read snapshot: this is not a snapshot",
+            ));
+        }
+
+        let version = u32::from_le_bytes(header[4..].try_into().unwrap());
+        if version != VERSION {
+            return Err(Error::new(ErrorKind::Snapshot, format!(
+                "cannot read snapshot: it's version {version}, but only version {VERSION} is supported"
+            )));
+        }
+
+        bincode::deserialize_from(reader)
+            .map_err(|e| Error::new(ErrorKind::Snapshot, format!("cannot read snapshot: {e}")))
+    }
+}
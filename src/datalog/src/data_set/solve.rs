@@ -0,0 +1,382 @@
+//! Finding the bindings which satisfy a list of goals.
+
+use std::{borrow::Cow, ops::Range};
+
+use crate::{
+    binding::Binding,
+    counter::Counter,
+    name_pool::{kind, ConstantId, Interner},
+    DataSet,
+};
+
+use super::{goal::Goal, limits::Stop, relation, Set, Term};
+
+/// Each variable a frame binds, or the constant a term must match.
+pub(super) enum Slot {
+    Constant(usize),
+    /// An index into the frame's variables.
+    Variable(usize),
+}
+
+/// One level of the search, which tries each of its choices in turn.
+struct Frame<'d> {
+    /// The variables this frame binds.
+    variables: Vec<usize>,
+
+    /// The values for `variables` which are left to try.
+    choices: Box<dyn Iterator<Item = Vec<usize>> + 'd>,
+}
+
+/// An iterator over every binding which satisfies all of some goals.
+///
+/// Goals are matched from left to right against the facts of their relations,
+/// binding variables as they go, so later goals only see tuples which agree
+/// with earlier ones. Any variable which is still unbound once every goal is
+/// satisfied is tried with every constant.
+///
+/// Foreign predicates can produce constants the data set hasn't seen yet.
+/// Those are given ids after all the data set's constants, and are kept in
+/// [`Solutions::into_new_constants`] until they can be added to the data set.
+pub(super) struct Solutions<'d> {
+    goals: Cow<'d, [Goal]>,
+    data: &'d DataSet,
+    binding: Vec<Option<usize>>,
+    stack: Vec<Frame<'d>>,
+    started: bool,
+    constants: Constants<'d>,
+
+    /// Which of the first goal's tuples to try, by position, if not all.
+    part: Option<Range<usize>>,
+
+    /// When to give up, and how many choices have been tried since that was
+    /// last checked.
+    stop: Option<&'d Stop<'d>>,
+    tried: usize,
+}
+
+/// How many choices are tried between checks that the search should go on.
+pub(super) const CHECK_EVERY: usize = 256;
+
+impl<'d> Solutions<'d> {
+    pub(super) fn new(
+        goals: impl Into<Cow<'d, [Goal]>>,
+        variables: usize,
+        data: &'d DataSet,
+    ) -> Solutions<'d> {
+        Solutions {
+            goals: goals.into(),
+            data,
+            binding: vec![None; variables],
+            stack: Vec::new(),
+            started: false,
+            constants: Constants::new(data),
+            part: None,
+            stop: None,
+            tried: 0,
+        }
+    }
+
+    /// Only try some of the tuples for the first goal, so that a big search
+    /// can be split up. The parts are in the same order as the solutions.
+    pub(super) fn part(mut self, part: Range<usize>) -> Self {
+        self.part = Some(part);
+        self
+    }
+
+    /// End the search early once `stop` says so.
+    pub(super) fn stop(mut self, stop: &'d Stop<'d>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    pub(super) fn data(&self) -> &'d DataSet {
+        self.data
+    }
+
+    /// The name of a constant, which may be one of the new constants.
+    pub(super) fn constant_name(&self, c: usize) -> &str {
+        self.constants.name(c)
+    }
+
+    /// The constants which foreign predicates produced that the data set
+    /// didn't already have, in id order.
+    pub(super) fn into_new_constants(self) -> Interner {
+        self.constants.into_new()
+    }
+
+    fn frame(&mut self, level: usize) -> Frame<'d> {
+        if level == self.goals.len() {
+            self.unbound_frame()
+        } else if self.data.foreign.contains_key(&self.goals[level].relation) {
+            self.foreign_frame(level)
+        } else {
+            self.relation_frame(level)
+        }
+    }
+
+    fn relation_frame(&self, level: usize) -> Frame<'d> {
+        let goal = &self.goals[level];
+        let (variables, slots) = slots(&goal.terms, &self.binding);
+        let count = variables.len();
+
+        let part = match &self.part {
+            Some(part) if level == 0 => part.clone(),
+            _ => 0..usize::MAX,
+        };
+
+        let choices = self.data.relations[goal.relation]
+            .iter()
+            .skip(part.start)
+            .take(part.len())
+            .filter_map(move |tuple| unify(&slots, count, tuple));
+
+        Frame {
+            variables,
+            choices: Box::new(choices),
+        }
+    }
+
+    fn foreign_frame(&mut self, level: usize) -> Frame<'d> {
+        let max = self.data.constants_count();
+        let (variables, choices) = foreign_choices(
+            &self.goals[level],
+            &mut self.binding,
+            max,
+            &mut self.constants,
+        );
+
+        Frame {
+            variables,
+            choices: Box::new(choices.into_iter()),
+        }
+    }
+
+    /// Try every constant for each variable which is still unbound.
+    fn unbound_frame(&self) -> Frame<'d> {
+        let variables = (0..self.binding.len())
+            .filter(|v| self.binding[*v].is_none())
+            .collect::<Vec<_>>();
+
+        let choices = every_value(variables.len(), self.data.constants_count());
+
+        Frame { variables, choices }
+    }
+}
+
+impl<'d> Iterator for Solutions<'d> {
+    type Item = Binding;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            let frame = self.frame(0);
+            self.stack.push(frame);
+        }
+
+        loop {
+            self.tried += 1;
+            if self.tried == CHECK_EVERY {
+                self.tried = 0;
+                if self.stop.is_some_and(|stop| stop.reason().is_some()) {
+                    self.stack.clear();
+                }
+            }
+
+            let frame = self.stack.last_mut()?;
+
+            for v in &frame.variables {
+                self.binding[*v] = None;
+            }
+
+            let Some(values) = frame.choices.next() else {
+                self.stack.pop();
+                continue;
+            };
+
+            for (v, c) in frame.variables.iter().zip(values) {
+                self.binding[*v] = Some(c);
+            }
+
+            let level = self.stack.len();
+            if level > self.goals.len() {
+                return Some(Binding::from(
+                    self.binding.iter().map(|c| c.unwrap()).collect::<Vec<_>>(),
+                ));
+            }
+
+            let frame = self.frame(level);
+            self.stack.push(frame);
+        }
+    }
+}
+
+/// The constants a search can use: the data set's own, and the new ones which
+/// foreign predicates produce along the way.
+pub(super) struct Constants<'d> {
+    data: &'d DataSet,
+    new: Interner,
+}
+
+impl<'d> Constants<'d> {
+    pub(super) fn new(data: &'d DataSet) -> Self {
+        Constants {
+            data,
+            new: Interner::default(),
+        }
+    }
+
+    pub(super) fn data(&self) -> &'d DataSet {
+        self.data
+    }
+
+    /// The number of constants, including the new ones.
+    pub(super) fn len(&self) -> usize {
+        self.data.constants_count() + self.new.len()
+    }
+
+    pub(super) fn name(&self, c: usize) -> &str {
+        let known = self.data.constants_count();
+        if c < known {
+            &self.data.names[ConstantId::new(c)]
+        } else {
+            &self.new[c - known]
+        }
+    }
+
+    pub(super) fn intern(&mut self, name: &str) -> usize {
+        match self.data.names.get::<kind::Constant>(name) {
+            Some(c) => c.index(),
+            None => self.data.constants_count() + self.new.intern(name),
+        }
+    }
+
+    pub(super) fn into_new(self) -> Interner {
+        self.new
+    }
+}
+
+/// Work out which variables in a list of terms are unbound, and what each
+/// term needs to match.
+pub(super) fn slots(terms: &[Term], binding: &[Option<usize>]) -> (Vec<usize>, Vec<Slot>) {
+    let mut variables = Vec::new();
+
+    let slots = terms
+        .iter()
+        .map(|term| match term {
+            Term::Constant(c) => Slot::Constant(*c),
+            Term::Variable(v) => match binding[*v] {
+                Some(c) => Slot::Constant(c),
+                None => match variables.iter().position(|u| u == v) {
+                    Some(i) => Slot::Variable(i),
+                    None => {
+                        variables.push(*v);
+                        Slot::Variable(variables.len() - 1)
+                    }
+                },
+            },
+        })
+        .collect();
+
+    (variables, slots)
+}
+
+/// Call a foreign predicate, trying each of the first `max` constants for any
+/// arguments which need to be bound but aren't. This returns the variables it
+/// binds, and each set of values for them.
+pub(super) fn foreign_choices(
+    goal: &Goal,
+    binding: &mut [Option<usize>],
+    max: usize,
+    constants: &mut Constants,
+) -> (Vec<usize>, Set<Vec<usize>>) {
+    let predicate = &constants.data().foreign[&goal.relation];
+
+    let mut unbound_inputs = Vec::new();
+    for (term, mode) in goal.terms.iter().zip(predicate.modes()) {
+        if let Term::Variable(v) = term {
+            if mode.is_bound() && binding[*v].is_none() && !unbound_inputs.contains(v) {
+                unbound_inputs.push(*v);
+            }
+        }
+    }
+
+    let mut choices = Set::default();
+    let mut variables = Vec::new();
+
+    for inputs in every_value(unbound_inputs.len(), max) {
+        for (v, c) in unbound_inputs.iter().zip(&inputs) {
+            binding[*v] = Some(*c);
+        }
+
+        let (frame_variables, slots) = slots(&goal.terms, binding);
+        let arguments = slots
+            .iter()
+            .map(|slot| match slot {
+                Slot::Constant(c) => Some(constants.name(*c)),
+                Slot::Variable(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        let results = predicate.call(&arguments);
+
+        for result in results {
+            if result.len() != slots.len() {
+                continue;
+            }
+
+            let tuple = result
+                .iter()
+                .map(|c| relation::id(constants.intern(c)))
+                .collect::<Vec<_>>();
+
+            if let Some(values) = unify(&slots, frame_variables.len(), &tuple) {
+                choices.insert([inputs.clone(), values].concat());
+            }
+        }
+
+        variables = [unbound_inputs.clone(), frame_variables].concat();
+    }
+
+    for v in &unbound_inputs {
+        binding[*v] = None;
+    }
+
+    (variables, choices)
+}
+
+/// Match a tuple against some slots, returning the values for the slots'
+/// variables if it fits.
+pub(super) fn unify(slots: &[Slot], variables: usize, tuple: &[u32]) -> Option<Vec<usize>> {
+    if slots.len() != tuple.len() {
+        return None;
+    }
+
+    let mut values = vec![None; variables];
+
+    for (slot, c) in slots.iter().zip(tuple) {
+        let c = &(*c as usize);
+        match slot {
+            Slot::Constant(k) if k != c => return None,
+            Slot::Constant(_) => {}
+            Slot::Variable(i) => match values[*i] {
+                Some(k) if k != *c => return None,
+                Some(_) => {}
+                None => values[*i] = Some(*c),
+            },
+        }
+    }
+
+    values.into_iter().collect()
+}
+
+/// Every list of `length` constants, from the first `max` constants.
+pub(super) fn every_value<'d>(
+    length: usize,
+    max: usize,
+) -> Box<dyn Iterator<Item = Vec<usize>> + 'd> {
+    if length == 0 {
+        Box::new(std::iter::once(Vec::new()))
+    } else {
+        Box::new(Counter::new(length, max))
+    }
+}
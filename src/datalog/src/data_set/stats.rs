@@ -0,0 +1,182 @@
+//! Keeping count of what running the rules does, to find the slow ones.
+
+use std::{cmp::Reverse, fmt, time::Duration};
+
+use crate::{
+    name_pool::{ConstantId, RelationId, VariableId},
+    parser::{Atom, Const, Relation, Rule as RuleSyntax, Term as TermSyntax, Var},
+    DataSet,
+};
+
+use super::{goal::Goal, rule::Rule, Term};
+
+/// What running the rules has done so far, from [`DataSet::stats`].
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    /// Each rule, in the order they were added.
+    pub rules: Vec<RuleStats>,
+    /// Each step towards the fixpoint, in order, across every run.
+    pub iterations: Vec<IterationStats>,
+}
+
+/// How one rule has done.
+#[derive(Debug, Default, Clone)]
+pub struct RuleStats {
+    /// The rule, as it would be written.
+    pub rule: String,
+    /// How many steps the rule was applied in.
+    pub fired: usize,
+    /// How many times its body was satisfied, including by facts that were
+    /// already known.
+    pub considered: usize,
+    /// How many new facts it derived.
+    pub derived: usize,
+    /// How long was spent applying it, added up across threads.
+    pub time: Duration,
+}
+
+/// What one step towards the fixpoint did.
+#[derive(Debug, Default, Clone)]
+pub struct IterationStats {
+    /// How long the step took.
+    pub time: Duration,
+    /// How many new facts each relation which grew got, by name.
+    pub growth: Vec<(String, usize)>,
+}
+
+/// The counts for one rule, without its text, which is only worked out when
+/// someone asks.
+#[derive(Debug, Default, Clone)]
+pub(super) struct Counts {
+    pub(super) fired: usize,
+    pub(super) considered: usize,
+    pub(super) derived: usize,
+    pub(super) time: Duration,
+}
+
+/// The counts as they're kept while running.
+#[derive(Debug, Default, Clone)]
+pub(super) struct Counters {
+    pub(super) rules: Vec<Counts>,
+    pub(super) iterations: Vec<(Duration, Vec<(usize, usize)>)>,
+}
+
+impl Counters {
+    pub(super) fn rule(&mut self, rule: usize) -> &mut Counts {
+        if self.rules.len() <= rule {
+            self.rules.resize(rule + 1, Counts::default());
+        }
+        &mut self.rules[rule]
+    }
+}
+
+impl DataSet {
+    /// How often each rule has fired, how much it found and how long it
+    /// took, and how much each relation grew in each step, across every
+    /// [`DataSet::run`].
+    pub fn stats(&self) -> Stats {
+        let rules = self
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                let counts = self.stats.rules.get(i).cloned().unwrap_or_default();
+                RuleStats {
+                    rule: self.rule_syntax(rule).to_string(),
+                    fired: counts.fired,
+                    considered: counts.considered,
+                    derived: counts.derived,
+                    time: counts.time,
+                }
+            })
+            .collect();
+
+        let iterations = self
+            .stats
+            .iterations
+            .iter()
+            .map(|(time, growth)| IterationStats {
+                time: *time,
+                growth: growth
+                    .iter()
+                    .map(|(rel, new)| (self.names[RelationId::new(*rel)].to_string(), *new))
+                    .collect(),
+            })
+            .collect();
+
+        Stats { rules, iterations }
+    }
+
+    pub(super) fn rule_syntax(&self, rule: &Rule) -> RuleSyntax {
+        let atom = |goal: &Goal| {
+            let terms = goal
+                .terms
+                .iter()
+                .map(|term| match term {
+                    Term::Constant(c) => {
+                        TermSyntax::Const(Const(self.names[ConstantId::new(*c)].into()))
+                    }
+                    Term::Variable(v) => {
+                        let name = VariableId::new(rule.variables()[*v]);
+                        TermSyntax::Var(Var(self.names[name].into()))
+                    }
+                })
+                .collect();
+            let name = &self.names[RelationId::new(goal.relation)];
+            Atom(Relation(name.into()), terms)
+        };
+
+        RuleSyntax(
+            atom(rule.head()),
+            rule.sub_goals().iter().map(atom).collect(),
+        )
+    }
+}
+
+/// A report with the slowest rules first, then the steps.
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rules: Vec<&RuleStats> = self.rules.iter().collect();
+        rules.sort_by_key(|rule| Reverse(rule.time));
+
+        writeln!(
+            f,
+            "{:>10} {:>7} {:>11} {:>8}  rule",
+            "time", "fired", "considered", "derived"
+        )?;
+        for rule in rules {
+            writeln!(
+                f,
+                "{:>10} {:>7} {:>11} {:>8}  {}",
+                format!("{:.1?}", rule.time),
+                rule.fired,
+                rule.considered,
+                rule.derived,
+                rule.rule
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:>10} {:>7}  new facts", "time", "step")?;
+        for (i, iteration) in self.iterations.iter().enumerate() {
+            let growth: Vec<String> = iteration
+                .growth
+                .iter()
+                .map(|(relation, new)| format!("{relation} +{new}"))
+                .collect();
+            writeln!(
+                f,
+                "{:>10} {:>7}  {}",
+                format!("{:.1?}", iteration.time),
+                i + 1,
+                if growth.is_empty() {
+                    "none".into()
+                } else {
+                    growth.join(", ")
+                }
+            )?;
+        }
+
+        Ok(())
+    }
+}
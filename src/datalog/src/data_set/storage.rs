@@ -0,0 +1,340 @@
+//! Keeping a data set in a directory on disk, so it survives restarts.
+//!
+//! The directory has two files:
+//!
+//! - `snapshot` is a generation number followed by a snapshot from
+//!   [`DataSet::write_snapshot`].
+//! - `log` is a write-ahead log of the facts, rules and constraints added
+//!   since that snapshot. It starts with the generation of the snapshot it
+//!   follows, and then has one record per fact, rule or constraint.
+//!
+//! Each log record is its length, a checksum, and then the entry encoded with
+//! [`bincode`]. If we crash part way through writing a record, the checksum
+//! won't match (or the record will be cut short), and that record and
+//! anything after it is dropped when the log is next opened.
+//!
+//! Compacting writes a new snapshot with the next generation number to a
+//! temporary file, renames it over the old one, and then does the same for an
+//! empty log. If we crash between those two renames, the old log's generation
+//! is behind the snapshot's, so we know everything in it is in the snapshot
+//! already and it's thrown away.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{parser::Rule as RuleSyntax, DataSet, Error};
+
+use super::constraints::Constraint;
+
+/// How many log records to write before compacting.
+const COMPACT_AFTER: usize = 10_000;
+
+/// Something which was added to a data set.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub(super) enum Entry {
+    Fact(String, Vec<String>),
+    Rule(RuleSyntax),
+    Constraint(Constraint),
+}
+
+/// The files backing a data set opened with [`DataSet::open`].
+#[derive(Debug)]
+pub(super) struct Storage {
+    directory: PathBuf,
+    generation: u64,
+    log: File,
+
+    /// The number of records in the log.
+    records: usize,
+
+    /// Entries which haven't been written to the log yet.
+    pending: Vec<Entry>,
+}
+
+impl Storage {
+    pub(super) fn push(&mut self, entry: Entry) {
+        self.pending.push(entry);
+    }
+}
+
+impl DataSet {
+    /// Open a data set which is kept in a directory, creating the directory
+    /// if it doesn't exist yet.
+    ///
+    /// Facts, rules and constraints added to the data set are saved when
+    /// [`DataSet::commit`] is called. Facts which are derived by rules aren't
+    /// saved until the next [`DataSet::compact`]. `.output` and `.bound`
+    /// directives are never saved, so they only last as long as this
+    /// [`DataSet`].
+    pub fn open(directory: impl AsRef<Path>) -> Result<DataSet, Error> {
+        let directory = directory.as_ref();
+        fs::create_dir_all(directory).map_err(|e| cannot("create", directory, e))?;
+
+        let snapshot_path = directory.join("snapshot");
+        let (generation, mut data) = match File::open(&snapshot_path) {
+            Ok(file) => {
+                let mut file = BufReader::new(file);
+                let generation =
+                    read_u64(&mut file).map_err(|e| cannot("read", &snapshot_path, e))?;
+                (generation, DataSet::read_snapshot(file)?)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => (0, DataSet::default()),
+            Err(e) => return Err(cannot("read", &snapshot_path, e)),
+        };
+
+        let log_path = directory.join("log");
+        let entries = match File::open(&log_path) {
+            Ok(file) => file
+                .metadata()
+                .and_then(|metadata| read_log(BufReader::new(file), metadata.len(), generation))
+                .map_err(|e| cannot("read", &log_path, e))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(cannot("read", &log_path, e)),
+        };
+
+        let (log, records) = match entries {
+            Some((entries, length)) => {
+                let mut log = OpenOptions::new()
+                    .write(true)
+                    .open(&log_path)
+                    .map_err(|e| cannot("open", &log_path, e))?;
+
+                // Drop anything after the last complete record.
+                log.set_len(length)
+                    .and_then(|_| log.seek(SeekFrom::End(0)))
+                    .map_err(|e| cannot("write", &log_path, e))?;
+
+                let records = entries.len();
+                for entry in entries {
+                    data.replay(entry)?;
+                }
+
+                (log, records)
+            }
+            None => (new_log(directory, generation)?, 0),
+        };
+
+        data.storage = Some(Storage {
+            directory: directory.into(),
+            generation,
+            log,
+            records,
+            pending: Vec::new(),
+        });
+
+        Ok(data)
+    }
+
+    /// Save any facts and rules added since the last commit, if this data set
+    /// was opened with [`DataSet::open`]. This also compacts the log every so
+    /// often.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        let Some(storage) = &mut self.storage else {
+            return Ok(());
+        };
+
+        if storage.pending.is_empty() {
+            return Ok(());
+        }
+
+        let log_path = storage.directory.join("log");
+        let start = storage
+            .log
+            .seek(SeekFrom::End(0))
+            .map_err(|e| cannot("write", &log_path, e))?;
+
+        if let Err(error) = write_records(&storage.log, &storage.pending, &log_path) {
+            // Cut off any part of a record which was written. Otherwise the
+            // next commit would go after it, and reading the log stops at the
+            // first torn record, so everything from then on would be lost.
+            let _ = storage
+                .log
+                .set_len(start)
+                .and_then(|_| storage.log.seek(SeekFrom::End(0)));
+            return Err(error);
+        }
+
+        storage.records += storage.pending.len();
+        storage.pending.clear();
+
+        if storage.records >= COMPACT_AFTER {
+            self.compact()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Replace the log with a snapshot of everything in the data set,
+    /// including derived facts, if this data set was opened with
+    /// [`DataSet::open`].
+    pub fn compact(&mut self) -> Result<(), Error> {
+        let Some(mut storage) = self.storage.take() else {
+            return Ok(());
+        };
+
+        // Put the storage back even if this fails, so we don't lose track of
+        // the pending entries.
+        let result = self.compact_into(&mut storage);
+        self.storage = Some(storage);
+        result
+    }
+
+    fn compact_into(&self, storage: &mut Storage) -> Result<(), Error> {
+        let generation = storage.generation + 1;
+        let snapshot_path = storage.directory.join("snapshot");
+        let temporary = storage.directory.join("snapshot.tmp");
+
+        let file = File::create(&temporary).map_err(|e| cannot("write", &temporary, e))?;
+        let mut writer = BufWriter::new(&file);
+        writer
+            .write_all(&generation.to_le_bytes())
+            .map_err(|e| cannot("write", &temporary, e))?;
+        self.write_snapshot(&mut writer)?;
+        writer
+            .flush()
+            .and_then(|_| file.sync_all())
+            .map_err(|e| cannot("write", &temporary, e))?;
+        drop(writer);
+
+        fs::rename(&temporary, &snapshot_path).map_err(|e| cannot("write", &snapshot_path, e))?;
+
+        storage.log = new_log(&storage.directory, generation)?;
+        storage.generation = generation;
+        storage.records = 0;
+        storage.pending.clear();
+
+        Ok(())
+    }
+
+    /// Add a fact, rule or constraint from the log, without logging it again.
+    fn replay(&mut self, entry: Entry) -> Result<(), Error> {
+        match entry {
+            Entry::Fact(relation, constants) => {
+                let constants = constants.iter().map(String::as_str).collect::<Vec<_>>();
+                self.insert_fact(&relation, &constants);
+            }
+            Entry::Rule(rule) => self.add_rule(rule)?,
+            Entry::Constraint(constraint) => self.constraint(constraint),
+        }
+        Ok(())
+    }
+}
+
+/// Append `entries` to the log, and wait until they're on disk.
+fn write_records(log: &File, entries: &[Entry], log_path: &Path) -> Result<(), Error> {
+    let mut writer = BufWriter::new(log);
+
+    for entry in entries {
+        let payload = bincode::serialize(entry).map_err(|e| cannot("write", log_path, e))?;
+
+        writer
+            .write_all(&(payload.len() as u32).to_le_bytes())
+            .and_then(|_| writer.write_all(&checksum(&payload).to_le_bytes()))
+            .and_then(|_| writer.write_all(&payload))
+            .map_err(|e| cannot("write", log_path, e))?;
+    }
+
+    writer
+        .flush()
+        .and_then(|_| log.sync_data())
+        .map_err(|e| cannot("write", log_path, e))
+}
+
+/// Make an empty log following the snapshot with the given generation.
+fn new_log(directory: &Path, generation: u64) -> Result<File, Error> {
+    let log_path = directory.join("log");
+    let temporary = directory.join("log.tmp");
+
+    let mut log = File::create(&temporary).map_err(|e| cannot("write", &temporary, e))?;
+    log.write_all(&generation.to_le_bytes())
+        .and_then(|_| log.sync_all())
+        .map_err(|e| cannot("write", &temporary, e))?;
+
+    fs::rename(&temporary, &log_path).map_err(|e| cannot("write", &log_path, e))?;
+
+    Ok(log)
+}
+
+/// Read the entries in a log which is `file_length` bytes long, and the
+/// length of the log up to the end of the last complete record. This is
+/// `None` if the log is older than the snapshot.
+fn read_log(
+    mut log: impl Read,
+    file_length: u64,
+    generation: u64,
+) -> std::io::Result<Option<(Vec<Entry>, u64)>> {
+    let log_generation = match read_u64(&mut log) {
+        Ok(g) => g,
+        // The log was never finished being made.
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    if log_generation != generation {
+        return Ok(None);
+    }
+
+    let mut entries = Vec::new();
+    let mut length = 8;
+
+    loop {
+        let mut header = [0; 8];
+        match log.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        let size = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let sum = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+        // A damaged length could be anything, so don't make room for more
+        // than the file has left.
+        if u64::from(size) > file_length.saturating_sub(length + header.len() as u64) {
+            break;
+        }
+
+        let mut payload = vec![0; size as usize];
+        match log.read_exact(&mut payload) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        if checksum(&payload) != sum {
+            break;
+        }
+
+        match bincode::deserialize(&payload) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => break,
+        }
+
+        length += header.len() as u64 + payload.len() as u64;
+    }
+
+    Ok(Some((entries, length)))
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// A 32-bit FNV-1a hash, which is plenty for noticing a torn write.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn cannot(verb: &str, path: &Path, error: impl std::fmt::Display) -> Error {
+    Error::new(
+        crate::ErrorKind::Io,
+        format!("cannot {verb} `{}`: {error}", path.display()),
+    )
+}
@@ -0,0 +1,416 @@
+//! Answering queries top-down, without running every rule first.
+//!
+//! Each goal is solved by looking at its relation's facts, and at the rules
+//! which could make more facts for it, whose bodies are solved in turn. Every
+//! call like `ancestor(luke, _)` gets a table of the answers found for it so
+//! far, so a recursive call to something already being worked out just reads
+//! its table instead of going round forever.
+//!
+//! A call which recursion leads back to can't be finished in one go, since its
+//! table was incomplete when it was read. So the outermost call of each group
+//! of calls which depend on each other is the group's leader, and solves the
+//! group again and again until nothing in it finds any new answers. Then every
+//! table in the group is complete, and is never worked out again. This is a
+//! simple form of SLG resolution, sometimes called linear tabling.
+//!
+//! With [`Limits`], every so often the search checks whether it should give
+//! up. The new answers in all the tables count as the new facts, and each time
+//! a leader solves its group again counts as an iteration.
+
+use std::{collections::BTreeMap, rc::Rc, time::Instant};
+
+use crate::{binding::Binding, data_set::goal::Goal, DataSet, Limits};
+
+use super::{
+    limits::{Reason, Stop},
+    rule::Rule,
+    solve::{every_value, foreign_choices, slots, unify, Constants, Slot, CHECK_EVERY},
+    Answer, Set, Tuple,
+};
+
+/// A relation, with the constants some of its arguments must be.
+type Call = (usize, Vec<Option<usize>>);
+
+/// The answers found for a call so far.
+struct Table {
+    answers: Rc<Set<Tuple>>,
+    complete: bool,
+}
+
+/// The tables for one query, and the calls being worked out.
+struct Tables<'d> {
+    data: &'d DataSet,
+    constants: Constants<'d>,
+    tables: BTreeMap<Call, Table>,
+
+    /// The calls being worked out, outermost first.
+    stack: Vec<Call>,
+
+    /// Calls which were worked out while some leader further up the stack
+    /// was, and which are only complete once it is.
+    waiting: Vec<Call>,
+
+    /// The number of answers found in all tables, to tell when a leader can
+    /// stop.
+    found: usize,
+
+    /// When to give up, if ever, and why the search did.
+    limits: Option<(&'d Limits, Stop<'d>)>,
+    stopped: Option<Reason>,
+    tried: usize,
+}
+
+/// There's no call on the stack which a call depends on.
+const INDEPENDENT: usize = usize::MAX;
+
+impl<'d> Tables<'d> {
+    fn new(data: &'d DataSet, limits: Option<&'d Limits>) -> Self {
+        let limits = limits.map(|limits| {
+            let deadline = limits.time.map(|time| Instant::now() + time);
+            (limits, Stop::new(limits, deadline, limits.facts))
+        });
+
+        Tables {
+            data,
+            constants: Constants::new(data),
+            tables: BTreeMap::new(),
+            stack: Vec::new(),
+            waiting: Vec::new(),
+            found: 0,
+            limits,
+            stopped: None,
+            tried: 0,
+        }
+    }
+
+    /// Should the search give up? The limits are only looked at every so
+    /// often. Once it's stopped, every call returns what it has so far.
+    fn should_stop(&mut self) -> bool {
+        let Some((_, stop)) = &self.limits else {
+            return false;
+        };
+
+        self.tried += 1;
+        if self.stopped.is_none() && self.tried == CHECK_EVERY {
+            self.tried = 0;
+            stop.found(self.found);
+            self.stopped = stop.reason();
+        }
+        self.stopped.is_some()
+    }
+
+    /// The answers to a call, along with how far down the stack the deepest
+    /// unfinished call it depends on is.
+    fn call(&mut self, call: Call) -> (Rc<Set<Tuple>>, usize) {
+        if let Some(table) = self.tables.get(&call) {
+            if table.complete {
+                return (table.answers.clone(), INDEPENDENT);
+            }
+            if let Some(depth) = self.stack.iter().position(|c| *c == call) {
+                return (table.answers.clone(), depth);
+            }
+        }
+
+        if !self.tables.contains_key(&call) {
+            let (relation, pattern) = &call;
+            let facts = self.data.relations[*relation]
+                .iter()
+                .filter(|tuple| matches(pattern, tuple))
+                .map(|tuple| Tuple(tuple.to_vec()))
+                .collect();
+            self.tables.insert(
+                call.clone(),
+                Table {
+                    answers: Rc::new(facts),
+                    complete: false,
+                },
+            );
+        }
+
+        let depth = self.stack.len();
+        let waiting = self.waiting.len();
+        self.stack.push(call.clone());
+
+        let mut rounds = 0;
+        let low = loop {
+            let found = self.found;
+            let low = self.evaluate(&call);
+            if low == INDEPENDENT || low < depth || self.found == found || self.should_stop() {
+                break low;
+            }
+
+            rounds += 1;
+            if let Some((limits, _)) = &self.limits {
+                if limits.iterations.is_some_and(|max| rounds >= max) {
+                    self.stopped = Some(Reason::Iterations);
+                    break low;
+                }
+            }
+        };
+
+        self.stack.pop();
+        let answers = self.tables[&call].answers.clone();
+        if low < depth {
+            self.waiting.push(call);
+            (answers, low)
+        } else {
+            for call in self.waiting.drain(waiting..).chain([call]) {
+                self.tables.get_mut(&call).unwrap().complete = true;
+            }
+            (answers, INDEPENDENT)
+        }
+    }
+
+    /// Try every rule for a call once, adding what they find to its table.
+    fn evaluate(&mut self, call: &Call) -> usize {
+        let (relation, pattern) = call;
+        let mut low = INDEPENDENT;
+        let mut found = Set::default();
+
+        for rule in self.data.rules.iter() {
+            if rule.relation() != *relation {
+                continue;
+            }
+
+            let mut binding = vec![None; rule.variable_count()];
+            if bind_head(rule.head(), pattern, &mut binding) {
+                low = low.min(self.body(rule, 0, &mut binding, &mut found));
+            }
+        }
+
+        let table = self.tables.get_mut(call).unwrap();
+        let answers = Rc::make_mut(&mut table.answers);
+        for tuple in found {
+            if answers.insert(tuple) {
+                self.found += 1;
+            }
+        }
+
+        low
+    }
+
+    /// Solve a rule's body from some sub-goal on, adding the head for each
+    /// solution to `found`.
+    fn body(
+        &mut self,
+        rule: &'d Rule,
+        level: usize,
+        binding: &mut [Option<usize>],
+        found: &mut Set<Tuple>,
+    ) -> usize {
+        let Some(goal) = rule.sub_goals().get(level) else {
+            // Like `DataSet::run`, any variable which is still unbound is
+            // tried with every constant.
+            for binding in self.unbound(binding) {
+                if self.should_stop() {
+                    break;
+                }
+                found.insert(rule.head().make_tuple(&binding));
+            }
+            return INDEPENDENT;
+        };
+
+        let (variables, choices, mut low) = self.choices(goal, binding);
+        for values in choices {
+            if self.should_stop() {
+                break;
+            }
+            for (v, c) in variables.iter().zip(values) {
+                binding[*v] = Some(c);
+            }
+            low = low.min(self.body(rule, level + 1, binding, found));
+        }
+        for v in &variables {
+            binding[*v] = None;
+        }
+
+        low
+    }
+
+    /// The ways a goal can be satisfied: the variables it binds, and each set
+    /// of values for them.
+    fn choices(
+        &mut self,
+        goal: &Goal,
+        binding: &mut [Option<usize>],
+    ) -> (Vec<usize>, Vec<Vec<usize>>, usize) {
+        if self.data.foreign.contains_key(&goal.relation) {
+            let max = self.constants.len();
+            let (variables, choices) = foreign_choices(goal, binding, max, &mut self.constants);
+            return (variables, choices.into_iter().collect(), INDEPENDENT);
+        }
+
+        let (variables, slots) = slots(&goal.terms, binding);
+        let pattern = slots
+            .iter()
+            .map(|slot| match slot {
+                Slot::Constant(c) => Some(*c),
+                Slot::Variable(_) => None,
+            })
+            .collect();
+
+        let (answers, low) = self.call((goal.relation, pattern));
+        let choices = answers
+            .iter()
+            .filter_map(|Tuple(tuple)| unify(&slots, variables.len(), tuple))
+            .collect();
+
+        (variables, choices, low)
+    }
+
+    /// Every complete binding, trying every constant for unbound variables.
+    fn unbound<'b>(&self, binding: &'b [Option<usize>]) -> impl Iterator<Item = Binding> + 'b {
+        let unbound: Vec<usize> = (0..binding.len())
+            .filter(|v| binding[*v].is_none())
+            .collect();
+
+        every_value(unbound.len(), self.constants.len()).map(move |values| {
+            let mut values = values.into_iter();
+            Binding::from(
+                binding
+                    .iter()
+                    .map(|c| c.unwrap_or_else(|| values.next().unwrap()))
+                    .collect::<Vec<_>>(),
+            )
+        })
+    }
+}
+
+/// Bind a rule head's variables to the constants a call needs, if they fit.
+fn bind_head(head: &Goal, pattern: &[Option<usize>], binding: &mut [Option<usize>]) -> bool {
+    if head.terms.len() != pattern.len() {
+        return false;
+    }
+
+    for (term, wanted) in head.terms.iter().zip(pattern) {
+        let Some(wanted) = wanted else { continue };
+        match term {
+            super::Term::Constant(c) if c != wanted => return false,
+            super::Term::Constant(_) => {}
+            super::Term::Variable(v) => match binding[*v] {
+                Some(c) if c != *wanted => return false,
+                Some(_) => {}
+                None => binding[*v] = Some(*wanted),
+            },
+        }
+    }
+
+    true
+}
+
+fn matches(pattern: &[Option<usize>], tuple: &[u32]) -> bool {
+    pattern.len() == tuple.len()
+        && pattern
+            .iter()
+            .zip(tuple)
+            .all(|(wanted, c)| wanted.is_none_or(|w| w == *c as usize))
+}
+
+/// One goal of the query, and the ways left to satisfy it.
+struct Frame {
+    variables: Vec<usize>,
+    choices: Box<dyn Iterator<Item = Vec<usize>>>,
+}
+
+/// The iterator behind [`DataSet::query_tabled`].
+///
+/// The query's goals are solved from left to right, and each one is only
+/// worked out when it's reached, so answers come out as they're found. If the
+/// search goes over its limits, the last item says why.
+pub(super) struct TabledAnswers<'d> {
+    tables: Tables<'d>,
+    goals: Vec<Goal>,
+    variables: Binding,
+    binding: Vec<Option<usize>>,
+    stack: Vec<Frame>,
+    started: bool,
+}
+
+impl<'d> TabledAnswers<'d> {
+    pub(super) fn new(
+        goals: Vec<Goal>,
+        variables: Binding,
+        data: &'d DataSet,
+        limits: Option<&'d Limits>,
+    ) -> Self {
+        TabledAnswers {
+            tables: Tables::new(data, limits),
+            binding: vec![None; variables.len()],
+            goals,
+            variables,
+            stack: Vec::new(),
+            started: false,
+        }
+    }
+
+    fn frame(&mut self, level: usize) -> Frame {
+        match self.goals.get(level) {
+            Some(goal) => {
+                let (variables, choices, _) = self.tables.choices(goal, &mut self.binding);
+                Frame {
+                    variables,
+                    choices: Box::new(choices.into_iter()),
+                }
+            }
+            None => {
+                let variables: Vec<usize> = (0..self.binding.len())
+                    .filter(|v| self.binding[*v].is_none())
+                    .collect();
+                let choices = every_value(variables.len(), self.tables.constants.len());
+                Frame { variables, choices }
+            }
+        }
+    }
+}
+
+impl Iterator for TabledAnswers<'_> {
+    type Item = Result<Answer, Reason>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            let frame = self.frame(0);
+            self.stack.push(frame);
+        }
+
+        loop {
+            // Whatever the search found before it stopped may be missing
+            // answers, so there's nothing more to give.
+            if let Some(reason) = self.tables.stopped.take() {
+                self.stack.clear();
+                return Some(Err(reason));
+            }
+
+            let frame = self.stack.last_mut()?;
+
+            for v in &frame.variables {
+                self.binding[*v] = None;
+            }
+
+            let Some(values) = frame.choices.next() else {
+                self.stack.pop();
+                continue;
+            };
+
+            for (v, c) in frame.variables.iter().zip(values) {
+                self.binding[*v] = Some(c);
+            }
+
+            let level = self.stack.len();
+            if level > self.goals.len() {
+                let binding =
+                    Binding::from(self.binding.iter().map(|c| c.unwrap()).collect::<Vec<_>>());
+                let constants = &self.tables.constants;
+                return Some(Ok(Answer::new(
+                    &binding,
+                    &self.variables,
+                    self.tables.data,
+                    |c| constants.name(c),
+                )));
+            }
+
+            let frame = self.frame(level);
+            self.stack.push(frame);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Writing down what running the rules does, as it happens, to watch facts
+//! being derived.
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::Write,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{
+    binding::Binding,
+    name_pool::{ConstantId, RelationId},
+    DataSet,
+};
+
+use super::{answer::Answer, rule::Rule};
+
+/// Where [`DataSet::run`] writes each step it takes, and every new fact with
+/// the rule and the binding of its variables which derived it.
+///
+/// ```text
+/// step 1
+///   path(a, b) by path(X, Y) :- edge(X, Y). with {X = a, Y = b}
+/// ```
+///
+/// A run which is stopped by its [`Limits`](crate::Limits) is traced up to
+/// that point, even though the facts it found aren't kept.
+pub struct Trace {
+    out: Mutex<Box<dyn Write + Send>>,
+    relations: BTreeSet<String>,
+}
+
+impl Trace {
+    /// Trace the facts in every relation to `out`.
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Trace {
+            out: Mutex::new(Box::new(out)),
+            relations: BTreeSet::new(),
+        }
+    }
+
+    /// Only trace the new facts in these relations. Every step is still
+    /// written.
+    pub fn relations(mut self, relations: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.relations = relations.into_iter().map(Into::into).collect();
+        self
+    }
+
+    fn wants(&self, relation: &str) -> bool {
+        self.relations.is_empty() || self.relations.contains(relation)
+    }
+
+    fn line(&self, line: fmt::Arguments) {
+        let mut out = self.out.lock().unwrap_or_else(PoisonError::into_inner);
+        // A trace is only there to be looked at, so not being able to write
+        // it isn't a reason to stop the run.
+        let _ = writeln!(out, "{line}");
+    }
+}
+
+impl fmt::Debug for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Trace")
+            .field("relations", &self.relations)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DataSet {
+    /// Trace what [`DataSet::run`] does from now on, or stop tracing with
+    /// `None`.
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace;
+    }
+
+    pub(super) fn trace_step(&self, step: usize) {
+        if let Some(trace) = &self.trace {
+            trace.line(format_args!("step {step}"));
+        }
+    }
+
+    /// Should the facts `rule` derives be traced? Then the binding each one
+    /// came from is kept until it's been written.
+    pub(super) fn traces(&self, rule: &Rule) -> bool {
+        self.trace
+            .as_ref()
+            .is_some_and(|trace| trace.wants(&self.names[RelationId::new(rule.relation())]))
+    }
+
+    /// Write down a new fact, from `rule` written out, with `binding` for its
+    /// variables.
+    pub(super) fn trace_fact(&self, rule: &Rule, text: &str, tuple: &[u32], binding: &Binding) {
+        let Some(trace) = &self.trace else {
+            return;
+        };
+
+        let constant = |c: usize| &self.names[ConstantId::new(c)];
+        let fact: Vec<&str> = tuple.iter().map(|c| constant(*c as usize)).collect();
+        let answer = Answer::new(binding, rule.variables(), self, constant);
+        trace.line(format_args!(
+            "  {}({}) by {text} with {answer}",
+            &self.names[RelationId::new(rule.relation())],
+            fact.join(", "),
+        ));
+    }
+}
@@ -0,0 +1,247 @@
+//! Warnings and notes, which don't stop a program from running, collected
+//! alongside the errors which do.
+
+use std::{collections::BTreeSet, fmt, str::FromStr, sync::Arc};
+
+use miette::{Diagnostic, LabeledSpan, NamedSource, Severity, SourceCode};
+
+use crate::Error;
+
+/// The kinds of [`Lint`], which can be silenced with an `.allow` directive
+/// like `.allow singleton-variable, unused-relation.`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LintKind {
+    /// The identifier policy changed an identifier.
+    Renamed,
+    /// Different identifiers ended up as the same name.
+    Collapsed,
+    /// A variable which only appears once in a rule.
+    SingletonVariable,
+    /// A relation which nothing reads, in a program with `.output`s.
+    UnusedRelation,
+    /// A rule with a body relation that can never have any facts.
+    NeverFires,
+    /// A fact which is already known.
+    DuplicateFact,
+    /// A recursive rule which can keep making new constants, in a program
+    /// without a `.bound`.
+    UnboundedRecursion,
+}
+
+impl LintKind {
+    pub const ALL: [LintKind; 7] = [
+        LintKind::Renamed,
+        LintKind::Collapsed,
+        LintKind::SingletonVariable,
+        LintKind::UnusedRelation,
+        LintKind::NeverFires,
+        LintKind::DuplicateFact,
+        LintKind::UnboundedRecursion,
+    ];
+
+    /// The diagnostic code for this kind of lint.
+    pub fn code(self) -> &'static str {
+        match self {
+            LintKind::Renamed => "datalog::lint::renamed",
+            LintKind::Collapsed => "datalog::lint::collapsed",
+            LintKind::SingletonVariable => "datalog::lint::singleton_variable",
+            LintKind::UnusedRelation => "datalog::lint::unused_relation",
+            LintKind::NeverFires => "datalog::lint::never_fires",
+            LintKind::DuplicateFact => "datalog::lint::duplicate_fact",
+            LintKind::UnboundedRecursion => "datalog::lint::unbounded_recursion",
+        }
+    }
+
+    /// The name used for this kind in `.allow` directives.
+    pub fn name(self) -> &'static str {
+        match self {
+            LintKind::Renamed => "renamed",
+            LintKind::Collapsed => "collapsed",
+            LintKind::SingletonVariable => "singleton-variable",
+            LintKind::UnusedRelation => "unused-relation",
+            LintKind::NeverFires => "never-fires",
+            LintKind::DuplicateFact => "duplicate-fact",
+            LintKind::UnboundedRecursion => "unbounded-recursion",
+        }
+    }
+}
+
+impl fmt::Display for LintKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for LintKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LintKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = LintKind::ALL.iter().map(|k| k.name()).collect();
+                format!("unknown lint `{s}`, expected one of {}", names.join(", "))
+            })
+    }
+}
+
+/// A warning or a note: a problem which doesn't stop the program from
+/// running, but probably means it doesn't do what was intended.
+#[derive(Debug)]
+pub struct Lint {
+    kind: LintKind,
+    severity: Severity,
+    reason: String,
+    labels: Vec<(usize, usize, String)>,
+    help: Option<String>,
+    source_code: Option<NamedSource>,
+}
+
+impl Lint {
+    pub fn warning(kind: LintKind, reason: impl Into<String>) -> Lint {
+        Lint {
+            kind,
+            severity: Severity::Warning,
+            reason: reason.into(),
+            labels: Vec::new(),
+            help: None,
+            source_code: None,
+        }
+    }
+
+    pub fn note(kind: LintKind, reason: impl Into<String>) -> Lint {
+        Lint {
+            severity: Severity::Advice,
+            ..Lint::warning(kind, reason)
+        }
+    }
+
+    pub fn with_labeled_span(mut self, start: usize, len: usize, label: impl Into<String>) -> Self {
+        self.labels.push((start, len, label.into()));
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Attach the source code that this lint's spans point into.
+    pub fn with_source_code(mut self, name: impl AsRef<str>, source: impl Into<Arc<str>>) -> Self {
+        self.source_code = Some(NamedSource::new(name, source.into()));
+        self
+    }
+
+    pub fn kind(&self) -> LintKind {
+        self.kind
+    }
+
+    /// Is this a warning, rather than a note?
+    pub fn is_warning(&self) -> bool {
+        self.severity == Severity::Warning
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = if self.is_warning() { "warning" } else { "note" };
+        write!(f, "{severity}: {}", self.reason)
+    }
+}
+
+impl std::error::Error for Lint {}
+
+impl Diagnostic for Lint {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new(self.kind.code()))
+    }
+
+    fn severity(&self) -> Option<Severity> {
+        Some(self.severity)
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.help
+            .as_ref()
+            .map(|help| Box::new(help) as Box<dyn fmt::Display>)
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        self.source_code.as_ref().map(|s| s as &dyn SourceCode)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        Some(Box::new(self.labels.iter().map(|(start, len, label)| {
+            LabeledSpan::new(Some(label.clone()), *start, *len)
+        })))
+    }
+}
+
+/// Everything there is to say about one piece of input: the errors which stop
+/// it from being used, and the lints which don't.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    errors: Vec<Error>,
+    lints: Vec<Lint>,
+    allowed: BTreeSet<LintKind>,
+}
+
+impl Diagnostics {
+    pub fn error(&mut self, error: Error) {
+        self.errors.push(error);
+    }
+
+    /// Add a lint, unless its kind has been allowed.
+    pub fn lint(&mut self, lint: Lint) {
+        if !self.allowed.contains(&lint.kind) {
+            self.lints.push(lint);
+        }
+    }
+
+    /// Silence a kind of lint, including any which were already added.
+    pub fn allow(&mut self, kind: LintKind) {
+        self.allowed.insert(kind);
+        self.lints.retain(|lint| lint.kind != kind);
+    }
+
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    pub fn lints(&self) -> &[Lint] {
+        &self.lints
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    /// The number of lints which are warnings, rather than notes.
+    pub fn warning_count(&self) -> usize {
+        self.lints.iter().filter(|lint| lint.is_warning()).count()
+    }
+
+    /// Attach the source code that all of these diagnostics point into.
+    pub fn with_source_code(self, name: impl AsRef<str>, source: impl Into<Arc<str>>) -> Self {
+        let source = source.into();
+        let name = name.as_ref();
+        Diagnostics {
+            errors: self
+                .errors
+                .into_iter()
+                .map(|e| e.with_source_code(name, source.clone()))
+                .collect(),
+            lints: self
+                .lints
+                .into_iter()
+                .map(|l| l.with_source_code(name, source.clone()))
+                .collect(),
+            allowed: self.allowed,
+        }
+    }
+
+    pub fn into_parts(self) -> (Vec<Error>, Vec<Lint>) {
+        (self.errors, self.lints)
+    }
+}
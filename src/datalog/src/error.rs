@@ -1,33 +1,152 @@
-use chumsky::{error::Simple, Span};
-use miette::{Diagnostic, NamedSource};
+use std::{fmt, sync::Arc};
+
+use chumsky::{
+    error::{Simple, SimpleReason},
+    Span,
+};
+use miette::{Diagnostic, LabeledSpan, NamedSource, SourceCode};
 use thiserror::Error;
 
-#[derive(Debug, Diagnostic, Error)]
-#[diagnostic()]
+/// What went wrong, which decides an [`Error`]'s diagnostic code.
+///
+/// The codes are stable, so tools can match on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorKind {
+    /// The input doesn't follow the grammar.
+    Syntax,
+    /// The [`IdentifierPolicy`][crate::IdentifierPolicy] rejected an
+    /// identifier.
+    Policy,
+    /// A variable where only constants are allowed, like in a fact.
+    ExpectedConstant,
+    /// A variable where a relation name should be.
+    ExpectedRelation,
+    /// A rule written with something like `<-` instead of `:-`.
+    RuleArrow,
+    /// An `.allow` directive names a lint which doesn't exist.
+    UnknownLint,
+    /// A file couldn't be read or written.
+    Io,
+    /// A file can't be loaded because of its format.
+    UnsupportedFormat,
+    /// Facts with the wrong number of constants for their relation.
+    Arity,
+    /// There's no relation with some name.
+    UnknownRelation,
+    /// A snapshot couldn't be read or written.
+    Snapshot,
+    /// An [`Answer`][crate::Answer] didn't fit the type it was deserialized
+    /// into.
+    Deserialize,
+    /// There were warnings, and warnings are treated as errors.
+    DeniedWarnings,
+    /// A file isn't laid out the way [`format`][crate::format()] would.
+    Unformatted,
+    /// Running the rules went over one of its [`Limits`][crate::Limits].
+    Limit,
+    /// Running the rules was stopped with a [`Cancel`][crate::Cancel] token.
+    Cancelled,
+    /// Some facts match a constraint, like `:- parent(X, X).`
+    Constraint,
+    /// A rule derives facts for a foreign predicate, or calls one before
+    /// an argument it needs is bound.
+    Foreign,
+}
+
+impl ErrorKind {
+    pub const ALL: [ErrorKind; 18] = [
+        ErrorKind::Syntax,
+        ErrorKind::Policy,
+        ErrorKind::ExpectedConstant,
+        ErrorKind::ExpectedRelation,
+        ErrorKind::RuleArrow,
+        ErrorKind::UnknownLint,
+        ErrorKind::Io,
+        ErrorKind::UnsupportedFormat,
+        ErrorKind::Arity,
+        ErrorKind::UnknownRelation,
+        ErrorKind::Snapshot,
+        ErrorKind::Deserialize,
+        ErrorKind::DeniedWarnings,
+        ErrorKind::Unformatted,
+        ErrorKind::Limit,
+        ErrorKind::Cancelled,
+        ErrorKind::Constraint,
+        ErrorKind::Foreign,
+    ];
+
+    /// The diagnostic code for this kind of error.
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::Syntax => "datalog::syntax",
+            ErrorKind::Policy => "datalog::policy",
+            ErrorKind::ExpectedConstant => "datalog::expected_constant",
+            ErrorKind::ExpectedRelation => "datalog::expected_relation",
+            ErrorKind::RuleArrow => "datalog::rule_arrow",
+            ErrorKind::UnknownLint => "datalog::unknown_lint",
+            ErrorKind::Io => "datalog::io",
+            ErrorKind::UnsupportedFormat => "datalog::unsupported_format",
+            ErrorKind::Arity => "datalog::arity",
+            ErrorKind::UnknownRelation => "datalog::unknown_relation",
+            ErrorKind::Snapshot => "datalog::snapshot",
+            ErrorKind::Deserialize => "datalog::deserialize",
+            ErrorKind::DeniedWarnings => "datalog::denied_warnings",
+            ErrorKind::Unformatted => "datalog::unformatted",
+            ErrorKind::Limit => "datalog::limit",
+            ErrorKind::Cancelled => "datalog::cancelled",
+            ErrorKind::Constraint => "datalog::constraint",
+            ErrorKind::Foreign => "datalog::foreign",
+        }
+    }
+
+    // Advice which applies to every error of this kind.
+    fn help(self) -> Option<&'static str> {
+        match self {
+            ErrorKind::ExpectedConstant => Some(
+                "names which start with an uppercase letter or `_` are variables, \
+                 and facts can only have constants",
+            ),
+            ErrorKind::ExpectedRelation => {
+                Some("relation names can't start with an uppercase letter or `_`")
+            }
+            ErrorKind::RuleArrow => Some("rules look like `ancestor(X, Y) :- parent(X, Y)`"),
+            _ => None,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<ErrorKind> {
+        ErrorKind::ALL.into_iter().find(|kind| kind.code() == code)
+    }
+}
+
+#[derive(Debug, Error)]
 #[error("error: {reason}")]
 pub struct Error {
+    kind: ErrorKind,
+
     reason: String,
 
     label: String,
 
-    #[label("{label}")]
     span: Option<(usize, usize)>,
 
-    #[related]
+    help: Option<String>,
+
     errors: Vec<Error>,
 
-    #[source_code]
-    source_code: NamedSource,
+    source_code: Option<NamedSource>,
 }
 
 impl Error {
-    pub fn new(reason: impl Into<String>) -> Error {
+    pub fn new(kind: ErrorKind, reason: impl Into<String>) -> Error {
         Error {
+            kind,
             reason: reason.into(),
             span: None,
             label: String::new(),
+            help: kind.help().map(str::to_string),
             errors: Vec::new(),
-            source_code: NamedSource::new("<unknown input>", ""),
+            source_code: None,
         }
     }
 
@@ -41,10 +160,53 @@ impl Error {
         self.with_span(start, len)
     }
 
-    pub fn with_source_code(mut self, source_code: NamedSource) -> Self {
-        self.source_code = source_code;
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Attach the source code that this error's spans point into, along with
+    /// any related errors.
+    pub fn with_source_code(mut self, name: impl AsRef<str>, source: impl Into<Arc<str>>) -> Self {
+        let source = source.into();
+        self.errors = self
+            .errors
+            .into_iter()
+            .map(|e| e.with_source_code(name.as_ref(), source.clone()))
+            .collect();
+        self.source_code = Some(NamedSource::new(name, source));
         self
     }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl Diagnostic for Error {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new(self.kind.code()))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.help
+            .as_ref()
+            .map(|help| Box::new(help) as Box<dyn fmt::Display>)
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        self.source_code.as_ref().map(|s| s as &dyn SourceCode)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let (start, len) = self.span?;
+        let label = LabeledSpan::new(Some(self.label.clone()), start, len);
+        Some(Box::new(std::iter::once(label)))
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        Some(Box::new(self.errors.iter().map(|e| e as &dyn Diagnostic)))
+    }
 }
 
 impl<E> From<Vec<E>> for Error
@@ -64,12 +226,74 @@ where
     }
 }
 
+impl Error {
+    /// Convert the errors from parsing `input`.
+    ///
+    /// miette can't draw a label at the very end of the input, so those point
+    /// at the last character instead.
+    pub(crate) fn from_syntax_errors(input: &str, errors: Vec<Simple<char>>) -> Error {
+        let trimmed = input.trim_end();
+        let last = trimmed.char_indices().last();
+
+        errors
+            .into_iter()
+            .map(|error| {
+                let error = Error::from(error);
+                match (error.span, last) {
+                    (Some((start, 0)), Some((i, c))) if start >= trimmed.len() => {
+                        error.with_span(i, c.len_utf8())
+                    }
+                    _ => error,
+                }
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
 impl From<Simple<char>> for Error {
     fn from(error: Simple<char>) -> Self {
-        Error::new("syntax error".to_string()).with_labeled_span(
+        let label = match error.reason() {
+            SimpleReason::Custom(reason) => reason.clone(),
+            SimpleReason::Unclosed { delimiter, .. } => format!("unclosed `{delimiter}`"),
+            SimpleReason::Unexpected => expected_found(&error),
+        };
+
+        // Errors we make ourselves carry their kind's code as their label.
+        let kind = error
+            .label()
+            .and_then(ErrorKind::from_code)
+            .unwrap_or(ErrorKind::Syntax);
+
+        Error::new(kind, "syntax error").with_labeled_span(
             error.span().start(),
             error.span().len(),
-            format!("{error}"),
+            label,
         )
     }
 }
+
+// Like "expected `(` or `,`, found `.`".
+fn expected_found(error: &Simple<char>) -> String {
+    let describe = |c: Option<&char>| match c {
+        Some(c) => format!("`{c}`"),
+        None => "end of input".to_string(),
+    };
+
+    let mut expected: Vec<_> = error.expected().map(|c| c.as_ref()).collect();
+    expected.sort();
+    let mut expected: Vec<String> = expected.into_iter().map(describe).collect();
+
+    // Parsers which can't list the characters they expect, like names, label
+    // their errors with a description instead.
+    if let Some(label) = error.label().filter(|l| ErrorKind::from_code(l).is_none()) {
+        expected.insert(0, label.to_string());
+    }
+    let found = describe(error.found());
+
+    match expected.split_last() {
+        None => format!("unexpected {found}"),
+        Some((last, [])) => format!("expected {last}, found {found}"),
+        Some((last, rest)) => format!("expected {} or {last}, found {found}", rest.join(", ")),
+    }
+}
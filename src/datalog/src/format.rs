@@ -0,0 +1,130 @@
+//! Laying out source code the same way every time, for
+//! `dexterous-datalog fmt`.
+
+use std::ops::Range;
+
+use crate::{
+    parser::{self, Rule, Statement},
+    BlockList, Error, Program,
+};
+
+/// Rules longer than this are wrapped, with one body atom per line.
+const WIDTH: usize = 80;
+
+const INDENT: &str = "    ";
+
+/// Lay out a program canonically: one statement per line, with the same
+/// spacing everywhere, long rules wrapped, and no more than one blank line in
+/// a row. Comments stay where they were, except for ones inside a statement,
+/// which move to just before it.
+///
+/// Identifiers are kept exactly as they're written, rather than going through
+/// an [`IdentifierPolicy`](crate::IdentifierPolicy).
+///
+/// ```
+/// let input = "parent( luke,anakin ).  % hi\n\n\n\nancestor(X,Y):-parent(X,Y).";
+/// assert_eq!(
+///     datalog::format(input).unwrap(),
+///     "parent(luke, anakin). % hi\n\nancestor(X, Y) :- parent(X, Y).\n",
+/// );
+/// ```
+pub fn format(input: &str) -> Result<String, Error> {
+    let program = Program::parse(input, BlockList::OFF)?;
+
+    let comments = parser::comments(input);
+
+    let mut items = Vec::new();
+    for (i, statement) in program.statements().iter().enumerate() {
+        items.push((
+            statement_range(input, program.span(i), &comments),
+            Item::Statement(statement),
+        ));
+    }
+
+    for comment in comments {
+        let text = input[comment.clone()].trim_end();
+        match items
+            .iter()
+            .find(|(range, _)| range.contains(&comment.start))
+        {
+            Some((range, _)) => items.push((range.start..range.start, Item::Comment(text))),
+            None => items.push((comment, Item::Comment(text))),
+        }
+    }
+
+    // The sort is stable, so comments moved before a statement stay in order
+    // and ahead of it.
+    items.sort_by_key(|(range, item)| (range.start, matches!(item, Item::Statement(_))));
+
+    let mut output = String::new();
+    let mut end = None;
+    for (range, item) in items {
+        let gap = end.map(|end| &input[end..range.start.max(end)]);
+        match item {
+            // A comment on the same line as the statement before it.
+            Item::Comment(text) if gap.is_some_and(|gap| !gap.contains('\n')) => {
+                output.pop();
+                output.push(' ');
+                output.push_str(text);
+            }
+            item => {
+                if gap.is_some_and(|gap| gap.matches('\n').count() > 1) {
+                    output.push('\n');
+                }
+                match item {
+                    Item::Statement(statement) => output.push_str(&layout(statement)),
+                    Item::Comment(text) => output.push_str(text),
+                }
+            }
+        }
+        output.push('\n');
+        end = end.max(Some(range.end));
+    }
+
+    Ok(output)
+}
+
+enum Item<'a> {
+    Statement(&'a Statement),
+    Comment(&'a str),
+}
+
+// A statement's span can run on over whitespace and comments after it, but
+// doesn't include the `.` at the end, so trim it back and then look past them
+// for the `.`.
+fn statement_range(input: &str, span: Range<usize>, comments: &[Range<usize>]) -> Range<usize> {
+    let mut end = span.end;
+    loop {
+        end = span.start + input[span.start..end].trim_end().len();
+        match comments.iter().find(|c| c.start < end && end <= c.end) {
+            Some(comment) => end = comment.start,
+            None => break,
+        }
+    }
+    let span = span.start..end;
+
+    loop {
+        let rest = &input[end..];
+        let trimmed = rest.trim_start();
+        end += rest.len() - trimmed.len();
+
+        if trimmed.starts_with('%') {
+            end += trimmed.find('\n').unwrap_or(trimmed.len());
+        } else if trimmed.starts_with('.') {
+            return span.start..end + 1;
+        } else {
+            return span;
+        }
+    }
+}
+
+fn layout(statement: &Statement) -> String {
+    let line = statement.to_string();
+    match statement {
+        Statement::Rule(Rule(head, body)) if line.chars().count() > WIDTH && !body.is_empty() => {
+            let body: Vec<String> = body.iter().map(|atom| format!("{INDENT}{atom}")).collect();
+            format!("{head} :-\n{}.", body.join(",\n"))
+        }
+        _ => line,
+    }
+}
@@ -1,14 +1,28 @@
 //! The datalog core.
 
+// `Error` carries its source code around for miette, so it is a bit big.
+#![allow(clippy::result_large_err)]
+
+pub mod build;
+
 mod binding;
 mod counter;
 mod data_set;
+mod diagnostics;
 mod error;
+mod format;
 mod name_pool;
 mod parser;
+mod policy;
 
 pub use crate::{
-    data_set::{Answer, DataSet},
-    error::Error,
-    parser::{BlockList, Program, Query, Repl},
+    data_set::{
+        Answer, Cancel, DataSet, IterationStats, Limits, Mode, RelationInfo, RuleStats, Stats,
+        Trace,
+    },
+    diagnostics::{Diagnostics, Lint, LintKind},
+    error::{Error, ErrorKind},
+    format::format,
+    parser::{Mention, MentionKind, Program, Query, Repl},
+    policy::{Alternating, BlockList, IdentifierPolicy},
 };
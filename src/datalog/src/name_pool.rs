@@ -1,29 +1,321 @@
-#[derive(Debug, Default)]
-pub(crate) struct NamePool {
-    pub(crate) names: Vec<String>,
+//! Giving names small ids, so the rest of the data set can work with numbers.
+//!
+//! Every distinct string is stored once, in an [`Interner`], however many
+//! kinds of thing it names. Relations, constants and variables then each have
+//! a [`NamePool`] of their own, so their ids stay dense: the constants are
+//! always `0..len`, which is what lets a relation be a `Vec` and an unbound
+//! variable be tried with every constant.
+
+use std::{collections::HashMap, fmt, hash::Hash, marker::PhantomData, ops::Index, sync::Arc};
+
+/// Strings, each stored once, with an id for each in the order they were
+/// first seen.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Interner {
+    strings: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, usize>,
 }
 
-impl NamePool {
+impl Interner {
     pub(crate) fn len(&self) -> usize {
-        self.names.len()
+        self.strings.len()
     }
 
-    pub(crate) fn add_name(&mut self, name: &str) -> usize {
-        for (i, n) in self.names.iter().enumerate() {
-            if name == n {
-                return i;
-            }
+    pub(crate) fn get(&self, string: &str) -> Option<usize> {
+        self.ids.get(string).copied()
+    }
+
+    pub(crate) fn intern(&mut self, string: &str) -> usize {
+        if let Some(id) = self.get(string) {
+            return id;
         }
 
-        let i = self.names.len();
-        self.names.push(name.into());
-        i
+        let id = self.strings.len();
+        let string: Arc<str> = string.into();
+        self.strings.push(string.clone());
+        self.ids.insert(string, id);
+        id
+    }
+
+    /// Every string, in id order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &str> {
+        self.strings.iter().map(|s| &**s)
     }
 }
 
-impl std::ops::Index<usize> for NamePool {
+impl Index<usize> for Interner {
     type Output = str;
     fn index(&self, index: usize) -> &Self::Output {
-        &self.names[index]
+        &self.strings[index]
+    }
+}
+
+/// The kinds of thing a data set names.
+pub(crate) mod kind {
+    #[derive(Debug)]
+    pub(crate) enum Relation {}
+    #[derive(Debug)]
+    pub(crate) enum Constant {}
+    #[derive(Debug)]
+    pub(crate) enum Variable {}
+}
+
+/// Picks out the [`NamePool`] for one kind of name.
+pub(crate) trait Kind: Sized {
+    fn pool(names: &Names) -> &NamePool<Self>;
+    fn pool_mut(names: &mut Names) -> &mut NamePool<Self>;
+}
+
+impl Kind for kind::Relation {
+    fn pool(names: &Names) -> &NamePool<Self> {
+        &names.relations
+    }
+    fn pool_mut(names: &mut Names) -> &mut NamePool<Self> {
+        &mut names.relations
+    }
+}
+
+impl Kind for kind::Constant {
+    fn pool(names: &Names) -> &NamePool<Self> {
+        &names.constants
+    }
+    fn pool_mut(names: &mut Names) -> &mut NamePool<Self> {
+        &mut names.constants
+    }
+}
+
+impl Kind for kind::Variable {
+    fn pool(names: &Names) -> &NamePool<Self> {
+        &names.variables
+    }
+    fn pool_mut(names: &mut Names) -> &mut NamePool<Self> {
+        &mut names.variables
+    }
+}
+
+/// The id of a name of some kind, counting from 0 for each kind.
+pub(crate) struct Id<K>(usize, PhantomData<fn() -> K>);
+
+pub(crate) type RelationId = Id<kind::Relation>;
+pub(crate) type ConstantId = Id<kind::Constant>;
+pub(crate) type VariableId = Id<kind::Variable>;
+
+impl<K> Id<K> {
+    pub(crate) fn new(index: usize) -> Self {
+        Id(index, PhantomData)
+    }
+
+    pub(crate) fn index(self) -> usize {
+        self.0
+    }
+}
+
+impl<K> Clone for Id<K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K> Copy for Id<K> {}
+
+impl<K> PartialEq for Id<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<K> Eq for Id<K> {}
+
+impl<K> Hash for Id<K> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl<K> fmt::Debug for Id<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Id({})", self.0)
+    }
+}
+
+/// The names of one kind, as ids into an [`Interner`].
+pub(crate) struct NamePool<K> {
+    strings: Vec<usize>,
+    ids: HashMap<usize, Id<K>>,
+}
+
+impl<K> Default for NamePool<K> {
+    fn default() -> Self {
+        NamePool {
+            strings: Vec::new(),
+            ids: HashMap::new(),
+        }
+    }
+}
+
+impl<K> fmt::Debug for NamePool<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.strings).finish()
+    }
+}
+
+/// Every name a data set has seen.
+#[derive(Debug, Default)]
+pub(crate) struct Names {
+    strings: Interner,
+    relations: NamePool<kind::Relation>,
+    constants: NamePool<kind::Constant>,
+    variables: NamePool<kind::Variable>,
+}
+
+impl Names {
+    /// The number of names of some kind.
+    pub(crate) fn len<K: Kind>(&self) -> usize {
+        K::pool(self).strings.len()
+    }
+
+    pub(crate) fn get<K: Kind>(&self, name: &str) -> Option<Id<K>> {
+        let string = self.strings.get(name)?;
+        K::pool(self).ids.get(&string).copied()
+    }
+
+    /// Forget the names of some kind after the first `len`. Their strings are
+    /// kept, in case they're added again.
+    pub(crate) fn truncate<K: Kind>(&mut self, len: usize) {
+        let pool = K::pool_mut(self);
+        for string in pool.strings.drain(len.min(pool.strings.len())..) {
+            pool.ids.remove(&string);
+        }
+    }
+
+    /// The id of a name, giving it the next one if it's new.
+    pub(crate) fn add<K: Kind>(&mut self, name: &str) -> Id<K> {
+        let string = self.strings.intern(name);
+        let pool = K::pool_mut(self);
+        *pool.ids.entry(string).or_insert_with(|| {
+            pool.strings.push(string);
+            Id::new(pool.strings.len() - 1)
+        })
+    }
+}
+
+impl<K: Kind> Index<Id<K>> for Names {
+    type Output = str;
+    fn index(&self, id: Id<K>) -> &Self::Output {
+        &self.strings[K::pool(self).strings[id.index()]]
+    }
+}
+
+/// Just the strings and each kind's ids for them are saved; the maps for
+/// looking names up are built again when they're loaded.
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use super::*;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    impl<K> NamePool<K> {
+        fn from_strings(strings: Vec<usize>) -> Self {
+            let ids = strings
+                .iter()
+                .enumerate()
+                .map(|(id, string)| (*string, Id::new(id)))
+                .collect();
+            NamePool { strings, ids }
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Saved {
+        strings: Vec<String>,
+        relations: Vec<usize>,
+        constants: Vec<usize>,
+        variables: Vec<usize>,
+    }
+
+    impl Serialize for Names {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            Saved {
+                strings: self.strings.iter().map(String::from).collect(),
+                relations: self.relations.strings.clone(),
+                constants: self.constants.strings.clone(),
+                variables: self.variables.strings.clone(),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Names {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let saved = Saved::deserialize(deserializer)?;
+
+            let mut strings = Interner::default();
+            for string in &saved.strings {
+                strings.intern(string);
+            }
+            if strings.len() != saved.strings.len() {
+                return Err(serde::de::Error::custom("a name is saved twice"));
+            }
+
+            let pools = [&saved.relations, &saved.constants, &saved.variables];
+            if pools
+                .iter()
+                .any(|pool| pool.iter().any(|s| *s >= strings.len()))
+            {
+                return Err(serde::de::Error::custom("a name id is out of range"));
+            }
+
+            Ok(Names {
+                strings,
+                relations: NamePool::from_strings(saved.relations),
+                constants: NamePool::from_strings(saved.constants),
+                variables: NamePool::from_strings(saved.variables),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_dense_for_each_kind() {
+        let mut names = Names::default();
+
+        let luke: ConstantId = names.add("luke");
+        let parent: RelationId = names.add("parent");
+        let leia: ConstantId = names.add("leia");
+        let luke_relation: RelationId = names.add("luke");
+
+        assert_eq!(luke.index(), 0);
+        assert_eq!(leia.index(), 1);
+        assert_eq!(parent.index(), 0);
+        assert_eq!(luke_relation.index(), 1);
+
+        assert_eq!(names.add::<kind::Constant>("luke"), luke);
+        assert_eq!(names.len::<kind::Constant>(), 2);
+        assert_eq!(names.len::<kind::Relation>(), 2);
+        assert_eq!(names.len::<kind::Variable>(), 0);
+
+        assert_eq!(&names[leia], "leia");
+        assert_eq!(&names[luke_relation], "luke");
+        assert_eq!(names.get::<kind::Relation>("leia"), None);
+        assert_eq!(names.get::<kind::Variable>("luke"), None);
+
+        // "luke" is only stored once.
+        assert_eq!(names.strings.len(), 3);
+    }
+
+    #[test]
+    fn many_names() {
+        let mut names = Names::default();
+        for i in 0..200_000 {
+            let id: ConstantId = names.add(&i.to_string());
+            assert_eq!(id.index(), i);
+        }
+        assert_eq!(
+            names.get::<kind::Constant>("123456").unwrap().index(),
+            123456
+        );
     }
 }
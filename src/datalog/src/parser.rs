@@ -2,32 +2,195 @@
 //!
 //! [wiki]: https://en.wikipedia.org/wiki/datalog#Syntax
 
-use std::fmt;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
 
 use chumsky::prelude::*;
 
-use crate::Error;
+use crate::{Diagnostics, Error, ErrorKind, IdentifierPolicy, Lint, LintKind};
 
-#[derive(Clone, Copy)]
-pub struct BlockList {
-    blocked: &'static str,
+/// The [`IdentifierPolicy`] shared by all the parts of a parser, along with
+/// every identifier it has been applied to.
+#[derive(Clone)]
+struct Policy {
+    policy: Rc<dyn IdentifierPolicy>,
+    identifiers: Rc<RefCell<Vec<Identifier>>>,
 }
 
-impl BlockList {
-    /// A block list which doesn't block anything.
-    pub const OFF: BlockList = BlockList { blocked: "" };
+/// An identifier as written in the source, and what the policy made of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Identifier {
+    pub(crate) span: Range<usize>,
+    pub(crate) original: String,
+    pub(crate) name: String,
+}
+
+impl Policy {
+    fn new(policy: impl IdentifierPolicy + 'static) -> Self {
+        Policy {
+            policy: Rc::new(policy),
+            identifiers: Rc::default(),
+        }
+    }
+
+    fn apply(&self, identifier: &str, span: Range<usize>) -> Result<String, String> {
+        let name = self.policy.apply(identifier)?;
+        self.identifiers.borrow_mut().push(Identifier {
+            span,
+            original: identifier.to_string(),
+            name: name.clone(),
+        });
+        Ok(name)
+    }
+
+    /// Every identifier in the order they appear in the source.
+    fn identifiers(&self) -> Vec<Identifier> {
+        // The parser backtracks, so the same identifier can be seen more than
+        // once.
+        let mut identifiers = self.identifiers.take();
+        identifiers.sort_by_key(|i| (i.span.start, i.span.end));
+        identifiers.dedup_by_key(|i| i.span.clone());
+        identifiers
+    }
+}
+
+/// Warn about identifiers which the policy changed, and about different
+/// identifiers which ended up with the same name.
+fn lint_identifiers(identifiers: &[Identifier], diagnostics: &mut Diagnostics) {
+    let mut sources: BTreeMap<&str, Vec<&Identifier>> = BTreeMap::new();
+
+    for (i, identifier) in identifiers.iter().enumerate() {
+        let Identifier {
+            span,
+            original,
+            name,
+        } = identifier;
 
-    /// Create a new [`BlockList`] which blocks a given set of [`char`]s.
-    pub fn from_disallowed(blocked: &'static str) -> BlockList {
-        BlockList { blocked }
+        // Only warn about the first place each identifier appears.
+        if identifiers[..i].iter().any(|i| &i.original == original) {
+            continue;
+        }
+
+        if original != name {
+            let label = match dropped(original, name) {
+                Ok(dropped) => format!("dropped {}", list(&dropped)),
+                Err(dropped) if dropped.is_empty() => format!("renamed to `{name}`"),
+                Err(dropped) => format!("dropped {} and renamed to `{name}`", list(&dropped)),
+            };
+            diagnostics.lint(
+                Lint::warning(
+                    LintKind::Renamed,
+                    format!("`{original}` is read as `{name}`"),
+                )
+                .with_labeled_span(span.start, span.len(), label),
+            );
+        }
+
+        sources.entry(name).or_default().push(identifier);
     }
 
-    /// Does this allow a given character?
-    fn is_allowed(&self, c: char) -> bool {
-        !self.blocked.contains(c.to_ascii_lowercase())
+    for (name, originals) in sources {
+        if originals.len() > 1 {
+            let all = if originals.len() == 2 { "both" } else { "all" };
+            let mut lint = Lint::warning(
+                LintKind::Collapsed,
+                format!(
+                    "{} are {all} read as `{name}`",
+                    list(&originals.iter().map(|i| &i.original).collect::<Vec<_>>())
+                ),
+            );
+            for identifier in originals {
+                lint = lint.with_labeled_span(
+                    identifier.span.start,
+                    identifier.span.len(),
+                    format!("`{}` first appears here", identifier.original),
+                );
+            }
+            diagnostics.lint(lint);
+        }
     }
 }
 
+// The characters which were left out of `original` to make `applied`. If
+// `applied` isn't just `original` with some characters left out, this is an
+// error holding the characters of `original` that don't appear in `applied`.
+fn dropped(original: &str, applied: &str) -> Result<Vec<char>, Vec<char>> {
+    let mut kept = applied.chars().peekable();
+    let mut dropped = Vec::new();
+
+    for c in original.chars() {
+        if kept.peek() == Some(&c) {
+            kept.next();
+        } else if !dropped.contains(&c) {
+            dropped.push(c);
+        }
+    }
+
+    if kept.peek().is_none() {
+        Ok(dropped)
+    } else {
+        dropped.retain(|&c| !applied.contains(c));
+        Err(dropped)
+    }
+}
+
+// Like "`a`, `b` and `c`".
+pub(crate) fn list(items: &[impl fmt::Display]) -> String {
+    let items: Vec<String> = items.iter().map(|item| format!("`{item}`")).collect();
+    match items.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {last}", rest.join(", ")),
+        None => String::new(),
+    }
+}
+
+// The characters of some input, with spans in bytes rather than characters,
+// so that they can be used to slice the input and to point miette at it.
+// Comments come through as spaces, so that the grammar can ignore them.
+fn stream(
+    input: &str,
+) -> chumsky::Stream<'_, char, Range<usize>, impl Iterator<Item = (char, Range<usize>)> + '_> {
+    let end = input.len();
+    let mut comments = comments(input).into_iter().peekable();
+    chumsky::Stream::from_iter(
+        end..end,
+        input.char_indices().map(move |(i, c)| {
+            while comments.next_if(|comment| comment.end <= i).is_some() {}
+            let commented = comments.peek().is_some_and(|comment| comment.contains(&i));
+            (if commented { ' ' } else { c }, i..i + c.len_utf8())
+        }),
+    )
+}
+
+/// Where the comments are in some input. They start with a `%` outside of a
+/// string, and run to the end of the line.
+pub(crate) fn comments(input: &str) -> Vec<Range<usize>> {
+    let mut comments = Vec::new();
+    let mut in_string = false;
+    let mut i = 0;
+
+    while let Some(c) = input[i..].chars().next() {
+        if c == '"' {
+            in_string = !in_string;
+        } else if c == '%' && !in_string {
+            let end = input[i..].find('\n').map_or(input.len(), |n| i + n);
+            comments.push(i..end);
+            i = end;
+            continue;
+        }
+        i += c.len_utf8();
+    }
+
+    comments
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Repl {
     Program(Program),
@@ -35,35 +198,301 @@ pub enum Repl {
 }
 
 impl Repl {
-    pub fn parse(input: &str, blocked: BlockList) -> Result<Self, Error> {
-        Self::parser(blocked).parse(input).map_err(Error::from)
+    pub fn parse(input: &str, policy: impl IdentifierPolicy + 'static) -> Result<Self, Error> {
+        match Self::parser(&Policy::new(policy)).parse(stream(input)) {
+            Ok(Repl::Program(program)) => Ok(Repl::Program(program.with_source(input))),
+            Ok(query) => Ok(query),
+            Err(errors) => Err(Error::from_syntax_errors(input, errors)),
+        }
+    }
+
+    /// Like [`parse`](Self::parse), but errors and lints go into
+    /// `diagnostics`, which also picks up any `.allow` directives.
+    pub fn parse_with_diagnostics(
+        input: &str,
+        policy: impl IdentifierPolicy + 'static,
+        diagnostics: &mut Diagnostics,
+    ) -> Option<Self> {
+        let policy = Policy::new(policy);
+        match Self::parser(&policy).parse(stream(input)) {
+            Ok(Repl::Program(program)) => Some(Repl::Program(
+                program.with_source(input).finish(&policy, diagnostics),
+            )),
+            Ok(query) => {
+                lint_identifiers(&policy.identifiers(), diagnostics);
+                Some(query)
+            }
+            Err(errors) => {
+                diagnostics.error(Error::from_syntax_errors(input, errors));
+                None
+            }
+        }
     }
 
-    fn parser(blocked: BlockList) -> impl Parser<char, Self, Error = Simple<char>> {
-        Program::parser(blocked)
+    fn parser(policy: &Policy) -> impl Parser<char, Self, Error = Simple<char>> {
+        Program::parser(policy)
             .map(Repl::Program)
-            .or(Query::parser(blocked).map(Repl::Query))
+            .or(Query::parser(policy).map(Repl::Query))
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Program(Vec<Statement>);
+pub struct Program {
+    statements: Vec<Statement>,
+
+    /// Where each statement is in the source.
+    spans: Vec<Range<usize>>,
+
+    /// Every identifier in the source, in order.
+    identifiers: Vec<Identifier>,
+
+    /// The source itself, and what it's called, for errors which come up
+    /// after the program has been added to a data set.
+    source: Arc<str>,
+    name: Arc<str>,
+
+    /// Where the files in `.input` and `.output` directives are, if not the
+    /// current working directory.
+    directory: Option<PathBuf>,
+}
 
 impl Program {
-    pub fn parse(input: &str, blocked: BlockList) -> Result<Self, Error> {
-        Self::parser(blocked).parse(input).map_err(Error::from)
+    pub fn parse(input: &str, policy: impl IdentifierPolicy + 'static) -> Result<Self, Error> {
+        Self::parser(&Policy::new(policy))
+            .parse(stream(input))
+            .map(|program| program.with_source(input))
+            .map_err(|errors| Error::from_syntax_errors(input, errors))
+    }
+
+    /// Like [`parse`](Self::parse), but errors and lints go into
+    /// `diagnostics`, which also picks up any `.allow` directives.
+    ///
+    /// If there are errors, the program has whichever statements could still
+    /// be parsed.
+    pub fn parse_with_diagnostics(
+        input: &str,
+        policy: impl IdentifierPolicy + 'static,
+        diagnostics: &mut Diagnostics,
+    ) -> Option<Self> {
+        let policy = Policy::new(policy);
+        let (program, errors) = Self::parser(&policy).parse_recovery(stream(input));
+        if !errors.is_empty() {
+            diagnostics.error(Error::from_syntax_errors(input, errors));
+        }
+        program.map(|program| program.with_source(input).finish(&policy, diagnostics))
+    }
+
+    /// Say where this program came from, like its file name. Errors about it
+    /// which come up later, like facts which break one of its constraints,
+    /// are reported against it.
+    pub fn with_name(mut self, name: impl AsRef<str>) -> Self {
+        self.name = name.as_ref().into();
+        self
+    }
+
+    /// Read and write the files named in `.input` and `.output` directives
+    /// relative to `directory`, usually the one the program's file is in,
+    /// instead of the current working directory.
+    pub fn with_directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.directory = Some(directory.into());
+        self
+    }
+
+    fn with_source(mut self, input: &str) -> Self {
+        self.source = input.into();
+        self
+    }
+
+    /// Every place a relation is named, in order.
+    ///
+    /// For programs parsed with [`parse`](Self::parse), the spans cover whole
+    /// statements rather than just the names.
+    pub fn mentions(&self) -> Vec<Mention> {
+        let mut mentions = Vec::new();
+
+        for (i, statement) in self.statements.iter().enumerate() {
+            let identifiers = self.identifiers(i);
+            let span = |k: usize| match identifiers.get(k) {
+                Some(identifier) => identifier.span.clone(),
+                None => self.span(i),
+            };
+            let mut mention = |Relation(name): &Relation, span, arity, kind| {
+                mentions.push(Mention {
+                    relation: name.clone(),
+                    span,
+                    arity,
+                    kind,
+                })
+            };
+
+            match statement {
+                Statement::Fact(Fact(relation, constants)) => {
+                    mention(relation, span(0), Some(constants.len()), MentionKind::Fact)
+                }
+                Statement::Rule(Rule(Atom(relation, terms), body)) => {
+                    mention(relation, span(0), Some(terms.len()), MentionKind::Head);
+
+                    let mut k = 1 + terms.len();
+                    for Atom(relation, terms) in body {
+                        mention(relation, span(k), Some(terms.len()), MentionKind::Body);
+                        k += 1 + terms.len();
+                    }
+                }
+                Statement::Input(Input(relation, _)) => {
+                    mention(relation, span(0), None, MentionKind::Input)
+                }
+                Statement::Output(Output(relation, _)) => {
+                    mention(relation, span(0), None, MentionKind::Output)
+                }
+                Statement::Constraint(body) => {
+                    let mut k = 0;
+                    for Atom(relation, terms) in body {
+                        mention(relation, span(k), Some(terms.len()), MentionKind::Body);
+                        k += 1 + terms.len();
+                    }
+                }
+                Statement::Allow(_) | Statement::Bound(_) => {}
+            }
+        }
+
+        mentions
     }
 
-    fn parser(blocked: BlockList) -> impl Parser<char, Self, Error = Simple<char>> {
-        statement(blocked)
-            .separated_by(just('.').padded())
-            .allow_trailing()
+    // A broken statement is skipped up to the next `.`, so that one parse can
+    // find the problems in all of them.
+    fn parser(policy: &Policy) -> impl Parser<char, Self, Error = Simple<char>> {
+        statement(policy)
+            .map_with_span(|statement, span| Some((statement, span)))
+            .then_ignore(just('.').padded().ignored().or(end()))
+            .recover_with(skip_until(['.'], |_| None).consume_end())
+            .repeated()
+            .padded_by(text::whitespace())
             .then_ignore(end())
-            .map(Program)
+            .map(|statements| {
+                let (statements, spans) = statements.into_iter().flatten().unzip();
+                Program {
+                    statements,
+                    spans,
+                    identifiers: Vec::new(),
+                    source: "".into(),
+                    name: "<program>".into(),
+                    directory: None,
+                }
+            })
+    }
+
+    // Fill in the identifiers the policy saw, lint them, and pick up the
+    // `.allow` directives.
+    fn finish(mut self, policy: &Policy, diagnostics: &mut Diagnostics) -> Self {
+        for statement in &self.statements {
+            if let Statement::Allow(kinds) = statement {
+                for kind in kinds {
+                    diagnostics.allow(*kind);
+                }
+            }
+        }
+
+        self.identifiers = policy.identifiers();
+        lint_identifiers(&self.identifiers, diagnostics);
+        self
     }
 
     pub(crate) fn statements(&self) -> &[Statement] {
-        &self.0
+        &self.statements
+    }
+
+    /// Does this program have any constraints, like `:- parent(X, X).`?
+    pub fn has_constraints(&self) -> bool {
+        self.statements
+            .iter()
+            .any(|statement| matches!(statement, Statement::Constraint(_)))
+    }
+
+    /// This program without its `.input` directives, so that adding it to a
+    /// data set doesn't read any files.
+    pub fn without_inputs(mut self) -> Self {
+        let (statements, spans) = self
+            .statements
+            .into_iter()
+            .zip(self.spans)
+            .filter(|(statement, _)| !matches!(statement, Statement::Input(_)))
+            .unzip();
+        self.statements = statements;
+        self.spans = spans;
+        self
+    }
+
+    /// Where the statement at some index is in the source.
+    pub(crate) fn span(&self, statement: usize) -> Range<usize> {
+        self.spans[statement].clone()
+    }
+
+    pub(crate) fn source(&self) -> &Arc<str> {
+        &self.source
+    }
+
+    pub(crate) fn name(&self) -> &Arc<str> {
+        &self.name
+    }
+
+    /// Where a file named in one of this program's directives is.
+    pub(crate) fn path(&self, path: &str) -> PathBuf {
+        match &self.directory {
+            Some(directory) => directory.join(path),
+            None => Path::new(path).into(),
+        }
+    }
+
+    /// The identifiers in the statement at some index, in order.
+    ///
+    /// These are only known for programs parsed with
+    /// [`parse_with_diagnostics`](Self::parse_with_diagnostics).
+    pub(crate) fn identifiers(&self, statement: usize) -> &[Identifier] {
+        let span = &self.spans[statement];
+        let start = self
+            .identifiers
+            .partition_point(|i| i.span.start < span.start);
+        let end = self.identifiers.partition_point(|i| i.span.end <= span.end);
+        &self.identifiers[start..end.max(start)]
+    }
+}
+
+/// A place where a [`Program`] names a relation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mention {
+    /// The relation's name.
+    pub relation: String,
+
+    /// Where the name is in the source.
+    pub span: Range<usize>,
+
+    /// The number of terms, or `None` for directives.
+    pub arity: Option<usize>,
+
+    pub kind: MentionKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MentionKind {
+    /// A fact, like `parent(padme, luke)`.
+    Fact,
+    /// The head of a rule.
+    Head,
+    /// An atom in the body of a rule.
+    Body,
+    /// An `.input` directive.
+    Input,
+    /// An `.output` directive.
+    Output,
+}
+
+impl MentionKind {
+    /// Does this kind of mention give the relation facts?
+    pub fn defines(self) -> bool {
+        matches!(
+            self,
+            MentionKind::Fact | MentionKind::Head | MentionKind::Input
+        )
     }
 }
 
@@ -72,12 +501,34 @@ impl Program {
 pub struct Query(pub Vec<Atom>);
 
 impl Query {
-    pub fn parse(input: &str, blocked: BlockList) -> Result<Self, Error> {
-        Self::parser(blocked).parse(input).map_err(Error::from)
+    pub fn parse(input: &str, policy: impl IdentifierPolicy + 'static) -> Result<Self, Error> {
+        Self::parser(&Policy::new(policy))
+            .parse(stream(input))
+            .map_err(|errors| Error::from_syntax_errors(input, errors))
+    }
+
+    /// Like [`parse`](Self::parse), but errors and lints go into
+    /// `diagnostics`.
+    pub fn parse_with_diagnostics(
+        input: &str,
+        policy: impl IdentifierPolicy + 'static,
+        diagnostics: &mut Diagnostics,
+    ) -> Option<Self> {
+        let policy = Policy::new(policy);
+        match Self::parser(&policy).parse(stream(input)) {
+            Ok(query) => {
+                lint_identifiers(&policy.identifiers(), diagnostics);
+                Some(query)
+            }
+            Err(errors) => {
+                diagnostics.error(Error::from_syntax_errors(input, errors));
+                None
+            }
+        }
     }
 
-    fn parser(blocked: BlockList) -> impl Parser<char, Self, Error = Simple<char>> {
-        atom(blocked)
+    fn parser(policy: &Policy) -> impl Parser<char, Self, Error = Simple<char>> {
+        atom(policy)
             .separated_by(just(',').padded())
             .map(Query)
             .then_ignore(end().or(just(".").ignored().then_ignore(end())))
@@ -86,19 +537,25 @@ impl Query {
 
 // ancestor(X, Y) :- parent(X, Z), ancestor(Z, Y).
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub(crate) struct Rule(pub Atom, pub Vec<Atom>);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rule(pub Atom, pub Vec<Atom>);
 
 impl Rule {
     #[cfg(test)]
-    pub(crate) fn parse(input: &str, blocked: BlockList) -> Result<Self, Error> {
-        Self::parser(blocked).parse(input).map_err(Error::from)
+    pub(crate) fn parse(
+        input: &str,
+        policy: impl IdentifierPolicy + 'static,
+    ) -> Result<Self, Error> {
+        Self::parser(&Policy::new(policy))
+            .parse(stream(input))
+            .map_err(|errors| Error::from_syntax_errors(input, errors))
     }
 
-    pub(crate) fn parser(blocked: BlockList) -> impl Parser<char, Rule, Error = Simple<char>> {
-        atom(blocked)
-            .then(just(":-").padded())
+    fn parser(policy: &Policy) -> impl Parser<char, Rule, Error = Simple<char>> {
+        atom(policy)
+            .then(arrow())
             .then(
-                atom(blocked)
+                atom(policy)
                     .separated_by(just(',').padded())
                     .allow_trailing(),
             )
@@ -110,54 +567,82 @@ impl Rule {
 pub(crate) enum Statement {
     Fact(Fact),
     Rule(Rule),
+    /// Like `:- parent(X, X)`, which no facts should ever match.
+    Constraint(Vec<Atom>),
+    Input(Input),
+    Output(Output),
+    Allow(Vec<LintKind>),
+    Bound(usize),
 }
 
 // Things like `parent(padme, luke).`
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Fact(pub Relation, pub Vec<Const>);
 
+// Things like `.input parent "parents.csv"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input(pub Relation, pub String);
+
+// Things like `.output ancestor "ancestors.csv"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output(pub Relation, pub String);
+
 // ancestor(X, Y)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Atom(pub Relation, pub Vec<Term>);
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Term {
     Const(Const),
     Var(Var),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Relation(pub String);
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Const(pub String);
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Var(pub String);
 
 // A name looks like a constant if there's at least one letter, and all letters
 // are lowercase.
-fn is_constant_name(name: &str) -> bool {
+pub(crate) fn is_constant_name(name: &str) -> bool {
     name.chars().any(|c| c.is_ascii_alphabetic())
         && name
             .chars()
             .all(|c| !c.is_ascii_alphabetic() || c.is_ascii_lowercase())
 }
 
-fn name(blocked: BlockList) -> impl Parser<char, String, Error = Simple<char>> {
-    text::ident().padded().map(move |name: String| {
-        let left: String = name.chars().filter(|c| blocked.is_allowed(*c)).collect();
+// A custom error, which remembers its kind for [`Error`].
+fn custom(kind: ErrorKind, span: Range<usize>, reason: impl ToString) -> Simple<char> {
+    Simple::custom(span, reason).with_label(kind.code())
+}
 
-        if left.is_empty() {
-            "no".into()
-        } else {
-            left
-        }
-    })
+fn name(policy: &Policy) -> impl Parser<char, String, Error = Simple<char>> {
+    let policy = policy.clone();
+    text::ident()
+        .map_err(|e: Simple<char>| e.with_label("a name"))
+        .validate(move |name: String, span: Range<usize>, emit| {
+            match policy.apply(&name, span.clone()) {
+                Ok(name) => name,
+                Err(reason) => {
+                    emit(custom(ErrorKind::Policy, span, reason));
+                    name
+                }
+            }
+        })
+        .padded()
 }
 
-fn term(blocked: BlockList) -> impl Parser<char, Term, Error = Simple<char>> {
-    name(blocked).map(|n| {
+fn term(policy: &Policy) -> impl Parser<char, Term, Error = Simple<char>> {
+    name(policy).map(|n| {
         if is_constant_name(&n) {
             Term::Const(Const(n))
         } else {
@@ -166,10 +651,11 @@ fn term(blocked: BlockList) -> impl Parser<char, Term, Error = Simple<char>> {
     })
 }
 
-fn constant(blocked: BlockList) -> impl Parser<char, Const, Error = Simple<char>> {
-    name(blocked).validate(|n, span, emit| {
+fn constant(policy: &Policy) -> impl Parser<char, Const, Error = Simple<char>> {
+    name(policy).validate(|n, span, emit| {
         if !is_constant_name(&n) {
-            emit(Simple::custom(
+            emit(custom(
+                ErrorKind::ExpectedConstant,
                 span,
                 format!("expected a constant but found variable `{n}`"),
             ))
@@ -178,10 +664,11 @@ fn constant(blocked: BlockList) -> impl Parser<char, Const, Error = Simple<char>
     })
 }
 
-fn relation(blocked: BlockList) -> impl Parser<char, Relation, Error = Simple<char>> {
-    name(blocked).validate(|n, span, emit| {
+fn relation(policy: &Policy) -> impl Parser<char, Relation, Error = Simple<char>> {
+    name(policy).validate(|n, span, emit| {
         if !is_constant_name(&n) {
-            emit(Simple::custom(
+            emit(custom(
+                ErrorKind::ExpectedRelation,
                 span,
                 format!("expected a relation but found variable `{n}`"),
             ))
@@ -190,10 +677,10 @@ fn relation(blocked: BlockList) -> impl Parser<char, Relation, Error = Simple<ch
     })
 }
 
-fn fact(blocked: BlockList) -> impl Parser<char, Fact, Error = Simple<char>> {
-    relation(blocked)
+fn fact(policy: &Policy) -> impl Parser<char, Fact, Error = Simple<char>> {
+    relation(policy)
         .then(
-            constant(blocked)
+            constant(policy)
                 .separated_by(just(',').padded())
                 .allow_trailing()
                 .delimited_by(just('(').padded(), just(')').padded()),
@@ -201,10 +688,10 @@ fn fact(blocked: BlockList) -> impl Parser<char, Fact, Error = Simple<char>> {
         .map(|(relation, terms)| Fact(relation, terms))
 }
 
-fn atom(blocked: BlockList) -> impl Parser<char, Atom, Error = Simple<char>> {
-    relation(blocked)
+fn atom(policy: &Policy) -> impl Parser<char, Atom, Error = Simple<char>> {
+    relation(policy)
         .then(
-            term(blocked)
+            term(policy)
                 .separated_by(just(',').padded())
                 .allow_trailing()
                 .delimited_by(just('(').padded(), just(')').padded()),
@@ -212,20 +699,169 @@ fn atom(blocked: BlockList) -> impl Parser<char, Atom, Error = Simple<char>> {
         .map(|(rel, terms)| Atom(rel, terms))
 }
 
-fn statement(blocked: BlockList) -> impl Parser<char, Statement, Error = Simple<char>> {
-    Rule::parser(blocked)
+// A double-quoted string with no escapes, used for file names.
+fn string() -> impl Parser<char, String, Error = Simple<char>> {
+    filter(|c: &char| *c != '"')
+        .repeated()
+        .delimited_by(just('"'), just('"'))
+        .collect()
+        .padded()
+}
+
+fn input(policy: &Policy) -> impl Parser<char, Input, Error = Simple<char>> {
+    just('.')
+        .ignore_then(text::keyword("input"))
+        .padded()
+        .ignore_then(relation(policy))
+        .then(string())
+        .map(|(relation, path)| Input(relation, path))
+}
+
+fn output(policy: &Policy) -> impl Parser<char, Output, Error = Simple<char>> {
+    just('.')
+        .ignore_then(text::keyword("output"))
+        .padded()
+        .ignore_then(relation(policy))
+        .then(string())
+        .map(|(relation, path)| Output(relation, path))
+}
+
+// The `:-` in a rule. Other arrows are understood, but they're errors.
+fn arrow() -> impl Parser<char, (), Error = Simple<char>> {
+    just(":-")
+        .or(just("<-"))
+        .or(just(":="))
+        .validate(|arrow, span, emit| {
+            if arrow != ":-" {
+                emit(custom(
+                    ErrorKind::RuleArrow,
+                    span,
+                    format!("expected `:-`, found `{arrow}`"),
+                ))
+            }
+        })
+        .padded()
+}
+
+// A fact like `r(x)` is also the head of a rule like `r(x) <- p(x)`. This
+// stops it from winning over the rule, so that the rule's arrow gets the
+// better error.
+fn not_an_arrow() -> impl Parser<char, (), Error = Simple<char>> {
+    let arrow = just(":-").or(just("<-")).or(just(":=")).padded();
+    end().or(arrow.not().ignored()).rewind()
+}
+
+// Like `:- parent(X, X)`: a rule without a head.
+fn constraint(policy: &Policy) -> impl Parser<char, Vec<Atom>, Error = Simple<char>> {
+    arrow().ignore_then(
+        atom(policy)
+            .separated_by(just(',').padded())
+            .at_least(1)
+            .allow_trailing(),
+    )
+}
+
+// Like `.allow singleton-variable, unused-relation`. Lint kinds aren't
+// identifiers, so they don't go through the policy.
+fn allow() -> impl Parser<char, Vec<LintKind>, Error = Simple<char>> {
+    let kind = filter(|c: &char| c.is_ascii_lowercase() || *c == '-')
+        .repeated()
+        .at_least(1)
+        .collect::<String>()
+        .validate(|kind, span, emit| {
+            kind.parse().unwrap_or_else(|reason| {
+                emit(custom(ErrorKind::UnknownLint, span, reason));
+                LintKind::Renamed
+            })
+        })
+        .padded();
+
+    just('.')
+        .ignore_then(text::keyword("allow"))
+        .padded()
+        .ignore_then(kind.separated_by(just(',').padded()).at_least(1))
+}
+
+// Like `.bound 1000`, the most steps a run of the rules takes.
+fn bound() -> impl Parser<char, usize, Error = Simple<char>> {
+    just('.')
+        .ignore_then(text::keyword("bound"))
+        .padded()
+        .ignore_then(text::int(10))
+        .validate(|steps: String, span, emit| {
+            steps.parse().unwrap_or_else(|_| {
+                emit(custom(ErrorKind::Syntax, span, "the bound is too big"));
+                usize::MAX
+            })
+        })
+        .padded()
+}
+
+fn statement(policy: &Policy) -> impl Parser<char, Statement, Error = Simple<char>> {
+    Rule::parser(policy)
         .map(Statement::Rule)
-        .or(fact(blocked).map(Statement::Fact))
+        .or(fact(policy)
+            .then_ignore(not_an_arrow())
+            .map(Statement::Fact))
+        .or(constraint(policy).map(Statement::Constraint))
+        .or(input(policy).map(Statement::Input))
+        .or(output(policy).map(Statement::Output))
+        .or(allow().map(Statement::Allow))
+        .or(bound().map(Statement::Bound))
+}
+
+// Programs print in the layout `dexterous-datalog fmt` uses for short
+// statements, one per line.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for statement in &self.statements {
+            writeln!(f, "{statement}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Fact(fact) => write!(f, "{fact}"),
+            Statement::Rule(rule) => write!(f, "{rule}"),
+            Statement::Constraint(body) => {
+                write!(f, ":- ")?;
+                comma_separated(f, body)?;
+                write!(f, ".")
+            }
+            Statement::Input(Input(Relation(name), path)) => {
+                write!(f, ".input {name} \"{path}\".")
+            }
+            Statement::Output(Output(Relation(name), path)) => {
+                write!(f, ".output {name} \"{path}\".")
+            }
+            Statement::Allow(kinds) => {
+                write!(f, ".allow ")?;
+                comma_separated(f, kinds)?;
+                write!(f, ".")
+            }
+            Statement::Bound(steps) => write!(f, ".bound {steps}."),
+        }
+    }
+}
+
+impl fmt::Display for Fact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Fact(Relation(name), constants) = self;
+        write!(f, "{name}(")?;
+        comma_separated(f, constants.iter().map(|Const(c)| c))?;
+        write!(f, ").")
+    }
 }
 
 impl fmt::Display for Rule {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let Rule(head, body) = self;
-        write!(f, "{} :-", head)?;
-        for clause in &body[..body.len() - 1] {
-            write!(f, " {},", clause)?;
-        }
-        write!(f, " {}.", body.last().unwrap())
+        write!(f, "{head} :- ")?;
+        comma_separated(f, body)?;
+        write!(f, ".")
     }
 }
 
@@ -233,21 +869,17 @@ impl fmt::Display for Query {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let Query(body) = self;
         write!(f, "?- ")?;
-        for term in &body[..body.len() - 1] {
-            write!(f, "{}, ", term)?;
-        }
-        write!(f, "{}.", body.last().unwrap())
+        comma_separated(f, body)?;
+        write!(f, ".")
     }
 }
 
 impl fmt::Display for Atom {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let Atom(Relation(name), body) = self;
-        write!(f, "{}(", name)?;
-        for term in &body[..body.len() - 1] {
-            write!(f, "{}, ", term)?;
-        }
-        write!(f, "{})", body.last().unwrap())
+        let Atom(Relation(name), terms) = self;
+        write!(f, "{name}(")?;
+        comma_separated(f, terms)?;
+        write!(f, ")")
     }
 }
 
@@ -260,9 +892,23 @@ impl fmt::Display for Term {
     }
 }
 
+fn comma_separated<T: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    items: impl IntoIterator<Item = T>,
+) -> fmt::Result {
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{item}")?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod parser_tests {
     use super::*;
+    use crate::BlockList;
 
     #[test]
     fn is_constant() {
@@ -276,7 +922,7 @@ mod parser_tests {
     fn empty() {
         let input = "";
         let syntax = Program::parse(input, BlockList::OFF).unwrap();
-        assert_eq!(syntax, Program(vec![]));
+        assert!(syntax.statements().is_empty());
     }
 
     #[test]
@@ -308,7 +954,7 @@ mod parser_tests {
     #[test]
     fn parse_fact() {
         let input = " fact ( a, b, c ) ";
-        let syntax = fact(BlockList::OFF).parse(input).unwrap();
+        let syntax = fact(&Policy::new(BlockList::OFF)).parse(input).unwrap();
         assert_eq!(
             syntax,
             Fact(
@@ -318,6 +964,29 @@ mod parser_tests {
         )
     }
 
+    #[test]
+    fn parse_input() {
+        let input = "p(a). .input parent \"data/parents.csv\". q(b).";
+        let syntax = Program::parse(input, BlockList::OFF).unwrap();
+        assert_eq!(
+            syntax.statements()[1],
+            Statement::Input(Input(Relation("parent".into()), "data/parents.csv".into()))
+        );
+    }
+
+    #[test]
+    fn parse_output() {
+        let input = ".output ancestor \"ancestors.json\".";
+        let syntax = Program::parse(input, BlockList::OFF).unwrap();
+        assert_eq!(
+            syntax.statements(),
+            &[Statement::Output(Output(
+                Relation("ancestor".into()),
+                "ancestors.json".into()
+            ))]
+        );
+    }
+
     #[test]
     fn parse_rule() {
         let input = "ancestor(X, Y) :- parent(X, Z), ancestor(Z, Y)";
@@ -343,4 +1012,111 @@ mod parser_tests {
             ),
         )
     }
+
+    #[test]
+    fn filter_warnings() {
+        let input = "father(anakin, luke). h(x, luke).";
+        let mut diagnostics = Diagnostics::default();
+        Program::parse_with_diagnostics(
+            input,
+            BlockList::from_disallowed("aeftx"),
+            &mut diagnostics,
+        )
+        .unwrap();
+
+        let reasons: Vec<String> = diagnostics
+            .lints()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                "warning: `father` is read as `hr`",
+                "warning: `anakin` is read as `nkin`",
+                "warning: `luke` is read as `luk`",
+                "warning: `x` is read as `no`",
+            ]
+        );
+    }
+
+    #[test]
+    fn collapse_warnings() {
+        let input = "father(luke). fathers(luke). hr(luke).";
+        let mut diagnostics = Diagnostics::default();
+        Program::parse_with_diagnostics(
+            input,
+            BlockList::from_disallowed("aeft"),
+            &mut diagnostics,
+        )
+        .unwrap();
+
+        assert_eq!(
+            diagnostics.lints().last().unwrap().to_string(),
+            "warning: `father` and `hr` are both read as `hr`"
+        );
+    }
+
+    #[test]
+    fn allow_directive() {
+        let input = ".allow renamed. father(luke).";
+        let mut diagnostics = Diagnostics::default();
+        let program = Program::parse_with_diagnostics(
+            input,
+            BlockList::from_disallowed("aeft"),
+            &mut diagnostics,
+        )
+        .unwrap();
+
+        assert_eq!(
+            program.statements()[0],
+            Statement::Allow(vec![LintKind::Renamed])
+        );
+        assert!(diagnostics.lints().is_empty());
+
+        let input = ".allow nothing-in-particular.";
+        assert!(Program::parse(input, BlockList::OFF).is_err());
+    }
+
+    #[test]
+    fn constraint() {
+        let mut diagnostics = Diagnostics::default();
+        let program = Program::parse_with_diagnostics(
+            ":- parent(X, X). p(a).",
+            BlockList::OFF,
+            &mut diagnostics,
+        )
+        .unwrap();
+        assert!(program.has_constraints());
+        assert_eq!(program.to_string(), ":- parent(X, X).\np(a).\n");
+
+        let mentions = program.mentions();
+        assert_eq!(mentions[0].relation, "parent");
+        assert_eq!(mentions[0].span, 3..9);
+        assert_eq!(mentions[0].kind, MentionKind::Body);
+
+        assert!(Program::parse(":- .", BlockList::OFF).is_err());
+    }
+
+    #[test]
+    fn bound_directive() {
+        let program = Program::parse(".bound 1000. p(a).", BlockList::OFF).unwrap();
+        assert_eq!(program.statements()[0], Statement::Bound(1000));
+        assert_eq!(program.to_string(), ".bound 1000.\np(a).\n");
+
+        assert!(Program::parse(".bound lots.", BlockList::OFF).is_err());
+        assert!(Program::parse(".bound 99999999999999999999999.", BlockList::OFF).is_err());
+    }
+
+    #[test]
+    fn without_inputs() {
+        let program = Program::parse(
+            "p(a). .input q \"q.csv\". .output p \"p.csv\".",
+            BlockList::OFF,
+        )
+        .unwrap()
+        .without_inputs();
+        assert_eq!(program.to_string(), "p(a).\n.output p \"p.csv\".\n");
+        assert_eq!(program.span(1), 24..41);
+    }
 }
@@ -0,0 +1,159 @@
+//! Identifier policies decide what happens to each identifier in the source,
+//! which is where the "one-handed" part of the language comes from.
+
+use std::{borrow::Cow, rc::Rc, sync::Arc};
+
+/// Something which checks or rewrites identifiers as they're parsed.
+pub trait IdentifierPolicy {
+    /// Work out the name to use for an identifier, or explain why the
+    /// identifier isn't allowed.
+    fn apply(&self, identifier: &str) -> Result<String, String>;
+}
+
+impl<P: IdentifierPolicy + ?Sized> IdentifierPolicy for Rc<P> {
+    fn apply(&self, identifier: &str) -> Result<String, String> {
+        (**self).apply(identifier)
+    }
+}
+
+impl<P: IdentifierPolicy + ?Sized> IdentifierPolicy for Arc<P> {
+    fn apply(&self, identifier: &str) -> Result<String, String> {
+        (**self).apply(identifier)
+    }
+}
+
+impl<P: IdentifierPolicy + ?Sized> IdentifierPolicy for Box<P> {
+    fn apply(&self, identifier: &str) -> Result<String, String> {
+        (**self).apply(identifier)
+    }
+}
+
+/// A policy which quietly drops any blocked characters from identifiers.
+///
+/// If every character in an identifier is blocked, it's replaced with `no`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockList {
+    blocked: Cow<'static, str>,
+}
+
+impl BlockList {
+    /// A block list which doesn't block anything.
+    pub const OFF: BlockList = BlockList {
+        blocked: Cow::Borrowed(""),
+    };
+
+    /// Create a new [`BlockList`] which blocks a given set of [`char`]s.
+    pub fn from_disallowed(blocked: impl Into<Cow<'static, str>>) -> BlockList {
+        BlockList {
+            blocked: blocked.into(),
+        }
+    }
+
+    /// Does this allow a given character?
+    fn is_allowed(&self, c: char) -> bool {
+        c.to_lowercase().all(|c| !self.blocked.contains(c))
+    }
+}
+
+impl IdentifierPolicy for BlockList {
+    fn apply(&self, identifier: &str) -> Result<String, String> {
+        let left: String = identifier.chars().filter(|c| self.is_allowed(*c)).collect();
+
+        if left.is_empty() {
+            Ok("no".into())
+        } else {
+            Ok(left)
+        }
+    }
+}
+
+/// A policy which rejects identifiers unless their letters alternate between
+/// the left and right hands.
+///
+/// Characters which aren't typed with either hand, like `_`, are skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alternating {
+    left: Cow<'static, str>,
+    right: Cow<'static, str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Hand {
+    Left,
+    Right,
+}
+
+impl Alternating {
+    /// Create a new [`Alternating`] policy from the keys typed with each hand.
+    pub fn new(
+        left: impl Into<Cow<'static, str>>,
+        right: impl Into<Cow<'static, str>>,
+    ) -> Alternating {
+        Alternating {
+            left: left.into(),
+            right: right.into(),
+        }
+    }
+
+    fn hand(&self, c: char) -> Option<Hand> {
+        let c = c.to_lowercase().next()?;
+        if self.left.contains(c) {
+            Some(Hand::Left)
+        } else if self.right.contains(c) {
+            Some(Hand::Right)
+        } else {
+            None
+        }
+    }
+}
+
+impl IdentifierPolicy for Alternating {
+    fn apply(&self, identifier: &str) -> Result<String, String> {
+        let mut last: Option<(char, Hand)> = None;
+
+        for c in identifier.chars() {
+            let Some(hand) = self.hand(c) else {
+                continue;
+            };
+
+            if let Some((previous, previous_hand)) = last {
+                if previous_hand == hand {
+                    let hand = match hand {
+                        Hand::Left => "left",
+                        Hand::Right => "right",
+                    };
+                    return Err(format!(
+                        "`{identifier}` doesn't alternate hands, `{previous}` and `{c}` are both typed with the {hand} hand"
+                    ));
+                }
+            }
+
+            last = Some((c, hand));
+        }
+
+        Ok(identifier.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_list() {
+        let policy = BlockList::from_disallowed("qwertasdfgzxcvb");
+        assert_eq!(policy.apply("parent"), Ok("pn".into()));
+        assert_eq!(policy.apply("Luke"), Ok("Luk".into()));
+        assert_eq!(policy.apply("wet"), Ok("no".into()));
+    }
+
+    #[test]
+    fn alternating() {
+        let policy = Alternating::new("qwertasdfgzxcvb", "yuiophjklnm");
+        assert!(policy.apply("with").is_ok());
+        assert!(policy.apply("Visible").is_ok());
+        assert!(policy.apply("parent").is_err());
+        assert!(policy.apply("a_b").is_err());
+        assert!(policy.apply("_").is_ok());
+    }
+}
@@ -1,4 +1,9 @@
-use datalog::{self, BlockList, DataSet, Program, Query};
+use datalog::{
+    self, Alternating, BlockList, DataSet, Diagnostics, ErrorKind, LintKind, MentionKind, Program,
+    Query,
+};
+use miette::Diagnostic;
+use std::path::PathBuf;
 
 fn star_wars_data() -> DataSet {
     let input = include_str!(concat!(
@@ -9,16 +14,26 @@ fn star_wars_data() -> DataSet {
     let program = Program::parse(input, BlockList::OFF).expect("sample code parses");
 
     let mut data = DataSet::default();
-    data.program(&program);
+    data.program(&program).expect("sample code loads");
     data
 }
 
+/// An empty directory for one test, which isn't shared with any other test
+/// running at the same time, even from another checkout.
+fn scratch(test: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("dexterous-datalog-{test}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
 #[test]
 fn single_clause_rule() {
     let input = " p(a). p(b). q(X) :- p(X). ";
     let program = Program::parse(input, BlockList::OFF).unwrap();
     let mut data = DataSet::default();
-    data.program(&program);
+    data.program(&program).unwrap();
 
     assert_eq!(data.len(), 2);
     data.run();
@@ -31,6 +46,875 @@ fn spoiler() {
     data.run();
 
     let query = Query::parse("father(X, luke).", BlockList::OFF).unwrap();
-    let answers = data.query(&query);
-    assert!(answers.iter().any(|a| a.to_string() == "{X = vader}"))
+    let mut answers = data.query(&query);
+    assert!(answers.any(|a| a.to_string() == "{X = vader}"))
+}
+
+#[test]
+fn load_csv() {
+    let mut data = DataSet::default();
+    data.load(
+        "parent",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/../../samples/parents.csv"),
+    )
+    .unwrap();
+
+    let input = " ancestor(X, Y) :- parent(X, Y). ancestor(X, Y) :- parent(X, Z), ancestor(Z, Y). ";
+    let program = Program::parse(input, BlockList::OFF).unwrap();
+    data.program(&program).unwrap();
+    data.run();
+
+    let query = Query::parse("ancestor(xerces, X).", BlockList::OFF).unwrap();
+    assert_eq!(data.query(&query).count(), 2);
+}
+
+#[test]
+fn input_directive() {
+    // The file is found next to the program, wherever the tests are run from.
+    let program = Program::parse(" .input parent \"parents.csv\". ", BlockList::OFF)
+        .unwrap()
+        .with_directory(concat!(env!("CARGO_MANIFEST_DIR"), "/../../samples"));
+    let mut data = DataSet::default();
+    data.program(&program).unwrap();
+    assert!(data.contains("parent", &["xerces", "brooke"]));
+}
+
+#[test]
+fn load_csv_wrong_arity() {
+    let path = scratch("wrong-arity").join("wrong-arity.csv");
+    std::fs::write(&path, "a,b\nc\nd,e\n").unwrap();
+
+    let mut data = DataSet::default();
+    assert!(data.load("p", &path).is_err());
+    assert!(data.is_empty());
+}
+
+#[test]
+fn output_directive() {
+    let path = scratch("output").join("output.json");
+    let input = format!(
+        " p(a, b). q(X, Y) :- p(Y, X). .output q \"{}\". ",
+        path.display()
+    );
+    let program = Program::parse(&input, BlockList::OFF).unwrap();
+    let mut data = DataSet::default();
+    data.program(&program).unwrap();
+    data.run();
+    data.write_outputs().unwrap();
+
+    let output = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(output, "[[\"b\",\"a\"]]\n");
+}
+
+#[test]
+fn builder() {
+    use datalog::build::{atom, constant, var};
+
+    let mut data = DataSet::default();
+    assert!(data.insert_fact("parent", &["padme", "luke"]));
+    assert!(data.insert_fact("parent", &["padme", "leia"]));
+    assert!(!data.insert_fact("parent", &["padme", "luke"]));
+    data.insert_fact("Weird Name", &["Not A Variable"]);
+    data.add_rule(atom("child", [var("X")]).when([atom("parent", [constant("padme"), var("X")])]))
+        .unwrap();
+    data.run();
+
+    let query = Query::parse("child(X).", BlockList::OFF).unwrap();
+    assert_eq!(data.query(&query).count(), 2);
+    assert_eq!(data.len(), 5);
+}
+
+#[test]
+fn answer_accessors() {
+    let mut data = star_wars_data();
+    data.run();
+
+    let query = Query::parse("kissed(X, Y), sibling(Y, X).", BlockList::OFF).unwrap();
+    let answers = data.query(&query).collect::<Vec<_>>();
+    assert!(!answers.is_empty());
+
+    for answer in answers {
+        assert_eq!(
+            answer.iter().map(|(v, _)| v).collect::<Vec<_>>(),
+            ["X", "Y"]
+        );
+        assert!(["luke", "leia"].contains(&&answer["X"]));
+        assert_eq!(answer.get("Z"), None);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn answer_deserialize() {
+    #[derive(serde::Deserialize)]
+    struct Kiss {
+        #[serde(rename = "X")]
+        x: String,
+        #[serde(rename = "Y")]
+        y: String,
+    }
+
+    let mut data = star_wars_data();
+    data.run();
+
+    let query = Query::parse("kissed(X, Y), sibling(Y, X).", BlockList::OFF).unwrap();
+    for answer in data.query(&query) {
+        let kiss: Kiss = answer.deserialize().unwrap();
+        let (x, y): (String, String) = answer.deserialize().unwrap();
+        assert_eq!((kiss.x, kiss.y), (x, y));
+        assert!(answer.deserialize::<(u32, String)>().is_err());
+    }
+}
+
+#[test]
+fn inspect() {
+    let mut data = star_wars_data();
+    data.run();
+
+    let father = data.relations().find(|r| r.name == "father").unwrap();
+    assert_eq!(father.arity, Some(2));
+    assert_eq!(father.len, data.tuples("father").unwrap().count());
+
+    assert!(data
+        .tuples("father")
+        .unwrap()
+        .any(|tuple| tuple == ["vader", "luke"]));
+    assert!(data.tuples("uncle").is_none());
+
+    assert!(data.contains("father", &["vader", "leia"]));
+    assert!(!data.contains("father", &["leia", "vader"]));
+    assert!(!data.contains("father", &["yoda", "luke"]));
+
+    assert!(data.has_constant("han"));
+    assert!(!data.has_constant("chewbacca"));
+}
+
+#[test]
+fn ground_query() {
+    let mut data = star_wars_data();
+    data.run();
+
+    let query = Query::parse("father(vader, luke).", BlockList::OFF).unwrap();
+    assert_eq!(data.query(&query).count(), 1);
+
+    let query = Query::parse("father(luke, vader).", BlockList::OFF).unwrap();
+    assert_eq!(data.query(&query).count(), 0);
+}
+
+#[test]
+fn foreign_predicate() {
+    use datalog::Mode;
+
+    let mut data = star_wars_data();
+    data.register_predicate("initial", &[Mode::Bound, Mode::Free], |args| {
+        let name = args[0].unwrap();
+        vec![vec![name.into(), name[..1].to_uppercase()]]
+    });
+
+    let input = " initials(X, I) :- parent(X, Y), initial(X, I). ";
+    let program = Program::parse(input, BlockList::OFF).unwrap();
+    data.program(&program).unwrap();
+    data.run();
+
+    assert!(data.contains("initials", &["padme", "P"]));
+    assert!(data.contains("initials", &["vader", "V"]));
+    assert!(!data.contains("initials", &["luke", "L"]));
+
+    let query = Query::parse("initial(X, I), kissed(X, han).", BlockList::OFF).unwrap();
+    let answers = data.query(&query).collect::<Vec<_>>();
+    assert_eq!(answers.len(), 1);
+    assert_eq!(&answers[0]["I"], "L");
+
+    // Rules can't call it before `X` is bound, or derive facts for it.
+    let rules = data.stats().rules.len();
+    for input in [
+        " initials(X, I) :- initial(X, I), parent(X, Y). ",
+        " initial(X, I) :- parent(X, I). ",
+    ] {
+        let program = Program::parse(input, BlockList::OFF).unwrap();
+        let error = data.program(&program).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Foreign);
+    }
+    assert_eq!(data.stats().rules.len(), rules);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn snapshot() {
+    let mut data = star_wars_data();
+    data.run();
+
+    let mut snapshot = Vec::new();
+    data.write_snapshot(&mut snapshot).unwrap();
+
+    let mut loaded = DataSet::read_snapshot(snapshot.as_slice()).unwrap();
+    assert_eq!(loaded.to_string(), data.to_string());
+    assert!(!loaded.is_dirty());
+
+    let program = Program::parse("mother(padme, ben).", BlockList::OFF).unwrap();
+    loaded.program(&program).unwrap();
+    loaded.run();
+    assert!(loaded.contains("sibling", &["luke", "ben"]));
+
+    assert!(DataSet::read_snapshot(&snapshot[1..]).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn database() {
+    let directory = scratch("database");
+
+    {
+        let mut data = DataSet::open(&directory).unwrap();
+        let program = Program::parse(
+            " p(a, b). p(b, c). q(X, Y) :- p(X, Y). q(X, Z) :- p(X, Y), q(Y, Z). :- p(X, X). \
+              .output q \"q.csv\". .bound 10. ",
+            BlockList::OFF,
+        )
+        .unwrap();
+        data.program(&program).unwrap();
+        data.commit().unwrap();
+        assert!(data.has_outputs());
+    }
+
+    // Simulate a crash part way through writing a record.
+    let log = directory.join("log");
+    let good = std::fs::read(&log).unwrap();
+    let mut bytes = good.clone();
+    bytes.extend([12, 0, 0, 0, 1, 2]);
+    std::fs::write(&log, bytes).unwrap();
+    drop(DataSet::open(&directory).unwrap());
+    assert_eq!(std::fs::read(&log).unwrap(), good);
+
+    // A damaged length is treated the same way, rather than believed.
+    let mut bytes = good.clone();
+    bytes.extend([0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 1, 2, 3]);
+    std::fs::write(&log, bytes).unwrap();
+
+    {
+        let mut data = DataSet::open(&directory).unwrap();
+        data.run();
+        assert!(data.contains("q", &["a", "c"]));
+        assert!(!data.has_outputs());
+
+        data.insert_fact("p", &["c", "d"]);
+        data.commit().unwrap();
+        data.compact().unwrap();
+        data.insert_fact("p", &["d", "e"]);
+        data.commit().unwrap();
+    }
+
+    let mut data = DataSet::open(&directory).unwrap();
+    data.run();
+    assert!(data.contains("q", &["a", "e"]));
+    assert_eq!(data.tuples("p").unwrap().count(), 4);
+
+    // Loading the same program again doesn't add its facts or rules twice, or
+    // log them again.
+    let log_length = std::fs::metadata(&log).unwrap().len();
+    let program = Program::parse(
+        " p(a, b). p(b, c). q(X, Y) :- p(X, Y). q(X, Z) :- p(X, Y), q(Y, Z). ",
+        BlockList::OFF,
+    )
+    .unwrap();
+    data.program(&program).unwrap();
+    data.commit().unwrap();
+    assert_eq!(data.stats().rules.len(), 2);
+    assert_eq!(std::fs::metadata(&log).unwrap().len(), log_length);
+    drop(data);
+
+    // The constraint was kept too, through the log and the snapshot, but the
+    // `.output` directive wasn't.
+    let mut data = DataSet::open(&directory).unwrap();
+    assert_eq!(data.stats().rules.len(), 2);
+    assert!(!data.has_outputs());
+    data.insert_fact("p", &["e", "e"]);
+    let mut diagnostics = Diagnostics::default();
+    data.check_constraints(&mut diagnostics);
+    assert_eq!(diagnostics.errors().len(), 1);
+}
+
+#[test]
+fn lints() {
+    let input = "
+        parent(a, b). parent(a, b).
+        ancestor(X, Y) :- parent(X, Y).
+        lonely(X) :- parent(X, Y).
+        odd(X) :- nothing(X).
+        .output ancestor \"ancestors.csv\".
+    ";
+    let mut diagnostics = Diagnostics::default();
+    let program = Program::parse_with_diagnostics(input, BlockList::OFF, &mut diagnostics).unwrap();
+    DataSet::default().check(&program, &mut diagnostics);
+
+    let kinds: Vec<LintKind> = diagnostics.lints().iter().map(|l| l.kind()).collect();
+    assert_eq!(
+        kinds,
+        [
+            LintKind::SingletonVariable,
+            LintKind::UnusedRelation,
+            LintKind::UnusedRelation,
+            LintKind::NeverFires,
+            LintKind::DuplicateFact,
+        ]
+    );
+    assert_eq!(diagnostics.warning_count(), 4);
+
+    let input = format!(".allow unused-relation, singleton-variable. {input}");
+    let mut diagnostics = Diagnostics::default();
+    let program =
+        Program::parse_with_diagnostics(&input, BlockList::OFF, &mut diagnostics).unwrap();
+    DataSet::default().check(&program, &mut diagnostics);
+
+    let kinds: Vec<LintKind> = diagnostics.lints().iter().map(|l| l.kind()).collect();
+    assert_eq!(kinds, [LintKind::NeverFires, LintKind::DuplicateFact]);
+}
+
+#[test]
+fn unbounded_recursion() {
+    use datalog::Mode;
+
+    let input = "
+        num(z).
+        num(Y) :- num(X), succ(X, Y).
+        even(z).
+        even(Y) :- odd(X), succ(X, Y).
+        odd(Y) :- even(X), succ(X, Y).
+        next(X, Y) :- num(X), num(Y), succ(X, Y).
+    ";
+    let data = || {
+        let mut data = DataSet::default();
+        data.register_predicate("succ", &[Mode::Bound, Mode::Free], |args| {
+            let n = args[0].unwrap();
+            vec![vec![n.to_string(), format!("s{n}")]]
+        });
+        data
+    };
+
+    let mut diagnostics = Diagnostics::default();
+    let program = Program::parse_with_diagnostics(input, BlockList::OFF, &mut diagnostics).unwrap();
+    data().check(&program, &mut diagnostics);
+
+    let lints: Vec<String> = diagnostics
+        .lints()
+        .iter()
+        .map(|lint| {
+            assert_eq!(lint.kind(), LintKind::UnboundedRecursion);
+            let labels: Vec<String> = lint
+                .labels()
+                .unwrap()
+                .map(|label| label.label().unwrap().to_string())
+                .collect();
+            labels.join("; ")
+        })
+        .collect();
+    assert_eq!(
+        lints,
+        [
+            "`succ` makes up a new constant for `Y`; which goes round num -> num",
+            "`succ` makes up a new constant for `Y`; which goes round even -> odd -> even",
+            "`succ` makes up a new constant for `Y`; which goes round odd -> even -> odd",
+        ]
+    );
+
+    // Nothing is made up without foreign predicates.
+    let mut diagnostics = Diagnostics::default();
+    DataSet::default().check(&program, &mut diagnostics);
+    assert!(diagnostics
+        .lints()
+        .iter()
+        .all(|lint| lint.kind() != LintKind::UnboundedRecursion));
+
+    // With a bound, the rules stop after that many steps, and that's fine.
+    let input = format!(".bound 10. {input}");
+    let mut diagnostics = Diagnostics::default();
+    let program =
+        Program::parse_with_diagnostics(&input, BlockList::OFF, &mut diagnostics).unwrap();
+    let mut data = data();
+    data.check(&program, &mut diagnostics);
+    assert!(diagnostics.lints().is_empty());
+
+    data.program(&program).unwrap();
+    data.run();
+    assert!(!data.is_dirty());
+    assert!(data.contains("num", &["ssssssssssz"]));
+    assert!(!data.contains("num", &["sssssssssssz"]));
+}
+
+#[test]
+fn constraints() {
+    let mut data = star_wars_data();
+    let input = "
+        ancestor(X, Y) :- parent(X, Y).
+        ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+        :- parent(X, X).
+        :- ancestor(X, Y), ancestor(Y, X).
+    ";
+    let program = Program::parse(input, BlockList::OFF).unwrap();
+    data.program(&program).unwrap();
+    data.run();
+
+    let mut diagnostics = Diagnostics::default();
+    data.check_constraints(&mut diagnostics);
+    assert!(!diagnostics.has_errors());
+
+    // The constraints are kept, so a fact added later still breaks them.
+    let program = Program::parse(" parent(luke, vader). ", BlockList::OFF).unwrap();
+    data.program(&program).unwrap();
+    data.run();
+
+    let mut diagnostics = Diagnostics::default();
+    data.check_constraints(&mut diagnostics);
+
+    let errors: Vec<String> = diagnostics
+        .errors()
+        .iter()
+        .map(|error| {
+            assert_eq!(error.kind(), ErrorKind::Constraint);
+            let label = error.labels().unwrap().next().unwrap();
+            assert_eq!(
+                &input[label.offset()..][..label.len()],
+                ":- ancestor(X, Y), ancestor(Y, X)"
+            );
+            error.to_string()
+        })
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            "error: constraint violated by {X = luke, Y = luke}",
+            "error: constraint violated by {X = luke, Y = vader}",
+            "error: constraint violated by {X = vader, Y = luke}",
+            "error: constraint violated by {X = vader, Y = vader}",
+        ]
+    );
+}
+
+#[test]
+fn error_codes() {
+    let error = Program::parse("p(a", BlockList::OFF).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Syntax);
+    assert_eq!(error.code().unwrap().to_string(), "datalog::syntax");
+
+    let error = Program::parse("p(X).", BlockList::OFF).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::ExpectedConstant);
+
+    let error = Program::parse("papa(a).", Alternating::new("qwert", "yuiop")).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Policy);
+
+    let error = DataSet::default().load("p", "p.json").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnsupportedFormat);
+
+    let mut diagnostics = Diagnostics::default();
+    let program =
+        Program::parse_with_diagnostics("q(X) :- p(X, Y).", BlockList::OFF, &mut diagnostics)
+            .unwrap();
+    DataSet::default().check(&program, &mut diagnostics);
+    let codes: Vec<String> = diagnostics
+        .lints()
+        .iter()
+        .map(|lint| lint.code().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        codes,
+        [
+            "datalog::lint::singleton_variable",
+            "datalog::lint::never_fires"
+        ]
+    );
+}
+
+#[test]
+fn error_recovery() {
+    let input = "p(a b). q(X) <- p(X). r(c) := p(c). s(X). t(";
+    let error = Program::parse(input, BlockList::OFF).unwrap_err();
+
+    let mut labels: Vec<String> = error
+        .related()
+        .unwrap()
+        .chain([&error as &dyn Diagnostic])
+        .map(|e| {
+            let code = e.code().unwrap();
+            let label = e.labels().unwrap().next().unwrap();
+            format!("{code}: {}", label.label().unwrap())
+        })
+        .collect();
+    labels.sort();
+
+    assert_eq!(
+        labels,
+        [
+            "datalog::expected_constant: expected a constant but found variable `X`",
+            "datalog::rule_arrow: expected `:-`, found `:=`",
+            "datalog::rule_arrow: expected `:-`, found `<-`",
+            "datalog::syntax: expected `)` or `,`, found `b`",
+            "datalog::syntax: expected a name or `)`, found end of input",
+        ]
+    );
+}
+
+#[test]
+fn mentions() {
+    // Spans are in bytes, even after a file name that isn't ASCII.
+    let input = ".output r \"café.csv\".\nparent(luke, anakin).\nancestor(X, Y) :- parent(X, Y).";
+    let mut diagnostics = Diagnostics::default();
+    let program = Program::parse_with_diagnostics(input, BlockList::OFF, &mut diagnostics).unwrap();
+
+    let mentions: Vec<_> = program
+        .mentions()
+        .into_iter()
+        .map(|m| (&input[m.span], m.arity, m.kind))
+        .collect();
+    assert_eq!(
+        mentions,
+        [
+            ("r", None, MentionKind::Output),
+            ("parent", Some(2), MentionKind::Fact),
+            ("ancestor", Some(2), MentionKind::Head),
+            ("parent", Some(2), MentionKind::Body),
+        ]
+    );
+
+    // A broken program still has the statements around the broken one.
+    let input = "p(a). q(X :- p(X). r(X) :- p(X).";
+    let program = Program::parse_with_diagnostics(input, BlockList::OFF, &mut diagnostics).unwrap();
+    let relations: Vec<_> = program.mentions().into_iter().map(|m| m.relation).collect();
+    assert_eq!(relations, ["p", "r", "p"]);
+}
+
+#[test]
+fn formatting() {
+    let input = "\n% Family.\nparent( luke,anakin ).   % his dad\nparent(leia,anakin).  parent(ben, han).\n\n\n\n.input mother \"moth%ers.csv\".\nancestor(X,Y):-parent(X,Y).\nancestor(X, Y) :- parent(X, Z), % recursive\n   ancestor(Z, Y).\nlong_relation_name(Alpha, Beta, Gamma) :- some_relation(Alpha, Beta), another_relation(Beta, Gamma).\nempty() :- .\n.allow singleton-variable,unused-relation.\n.output ancestor \"a.csv\"\n% The end.";
+    let expected = "% Family.
+parent(luke, anakin). % his dad
+parent(leia, anakin).
+parent(ben, han).
+
+.input mother \"moth%ers.csv\".
+ancestor(X, Y) :- parent(X, Y).
+% recursive
+ancestor(X, Y) :- parent(X, Z), ancestor(Z, Y).
+long_relation_name(Alpha, Beta, Gamma) :-
+    some_relation(Alpha, Beta),
+    another_relation(Beta, Gamma).
+empty() :- .
+.allow singleton-variable, unused-relation.
+.output ancestor \"a.csv\".
+% The end.
+";
+
+    let formatted = datalog::format(input).unwrap();
+    assert_eq!(formatted, expected);
+    assert_eq!(datalog::format(&formatted).unwrap(), formatted);
+
+    // Formatting doesn't change what a program means.
+    let before = Program::parse(input, BlockList::OFF).unwrap();
+    let after = Program::parse(&formatted, BlockList::OFF).unwrap();
+    assert_eq!(before.to_string(), after.to_string());
+
+    let error = datalog::format("p(a) :- q(").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Syntax);
+}
+
+#[test]
+fn tabled_queries() {
+    use std::collections::BTreeSet;
+
+    let queries = [
+        "father(X, luke)",
+        "alias(vader, X)",
+        "alias(X, Y)",
+        "sibling(luke, X)",
+        "kissed(X, han)",
+        "parent(P, leia), kissed(leia, Y)",
+        "kissed(X, X)",
+        "father(anakin, leia)",
+        "nobody(X)",
+    ];
+
+    let mut bottom_up = star_wars_data();
+    bottom_up.run();
+
+    for query in queries {
+        let query = Query::parse(query, BlockList::OFF).unwrap();
+        let expected: BTreeSet<_> = bottom_up.query(&query).collect();
+
+        let mut top_down = star_wars_data();
+        let answers: BTreeSet<_> = top_down.query_tabled(&query).collect();
+        assert_eq!(answers, expected, "{query}");
+        // Nothing was added to the data set along the way.
+        assert_eq!(top_down.len(), star_wars_data().len());
+    }
+
+    // Left recursion, rules with variables that aren't bound by their body,
+    // and foreign predicates.
+    let input = "
+        edge(a, b). edge(b, c). edge(c, a). edge(c, d).
+        path(X, Y) :- path(X, Z), edge(Z, Y).
+        path(X, Y) :- edge(X, Y).
+        anything(X, Y) :- edge(X, _Z).
+        loud(X, Y) :- path(a, X), shout(X, Y).
+    ";
+    let program = Program::parse(input, BlockList::OFF).unwrap();
+    let data = || {
+        let mut data = DataSet::default();
+        data.register_predicate(
+            "shout",
+            &[datalog::Mode::Bound, datalog::Mode::Free],
+            |args| {
+                let quiet = args[0].unwrap();
+                vec![vec![quiet.to_string(), quiet.to_uppercase()]]
+            },
+        );
+        data.program(&program).unwrap();
+        data
+    };
+
+    let mut bottom_up = data();
+    bottom_up.run();
+    for query in [
+        "path(a, X)",
+        "path(X, a)",
+        "path(X, Y)",
+        "anything(d, Y)",
+        "loud(X, Y)",
+    ] {
+        let query = Query::parse(query, BlockList::OFF).unwrap();
+        let expected: BTreeSet<_> = bottom_up.query(&query).collect();
+        let answers: BTreeSet<_> = data().query_tabled(&query).collect();
+        assert_eq!(answers, expected, "{query}");
+    }
+}
+
+#[test]
+fn parallel_rules() {
+    // Enough `pair` facts that rules starting with it are split into parts,
+    // and a foreign predicate which makes new constants in each part.
+    let mut input = String::new();
+    for i in 0..40 {
+        input.push_str(&format!("num(n{i}). "));
+    }
+    input.push_str(
+        "pair(X, Y) :- num(X), num(Y).
+         swapped(Y, X) :- pair(X, Y).
+         loud(X, Z) :- pair(X, Y), shout(Y, Z).
+         louder(Z) :- loud(X, Z), num(X).",
+    );
+    let program = Program::parse(&input, BlockList::OFF).unwrap();
+
+    let run = |threads| {
+        let mut data = DataSet::default();
+        data.set_threads(threads);
+        data.register_predicate(
+            "shout",
+            &[datalog::Mode::Bound, datalog::Mode::Free],
+            |args| {
+                let quiet = args[0].unwrap();
+                vec![vec![
+                    quiet.to_string(),
+                    format!("{}!", quiet.to_uppercase()),
+                ]]
+            },
+        );
+        data.program(&program).unwrap();
+        data.run();
+        data.to_string()
+    };
+
+    let sequential = run(1);
+    assert_eq!(sequential.lines().count(), 40 + 1600 + 1600 + 1600 + 40);
+    assert_eq!(run(4), sequential);
+    assert_eq!(run(0), sequential);
+}
+
+#[test]
+fn limits() {
+    use datalog::{Cancel, Limits, Mode};
+    use std::time::{Duration, Instant};
+
+    // Counting up never finishes, since `succ` always makes a new number.
+    let program =
+        Program::parse(" num(z). num(Y) :- num(X), succ(X, Y). ", BlockList::OFF).unwrap();
+    let data = || {
+        let mut data = DataSet::default();
+        data.register_predicate("succ", &[Mode::Bound, Mode::Free], |args| {
+            let n = args[0].unwrap();
+            vec![vec![n.to_string(), format!("s{n}")]]
+        });
+        data.program(&program).unwrap();
+        data
+    };
+
+    let run = |limits: Limits| {
+        let mut data = data();
+        let error = data.run_with_limits(&limits).unwrap_err();
+
+        // Nothing the rules found is kept, not even new constants, and the
+        // stats don't count it either.
+        assert_eq!(data.len(), 1);
+        assert!(!data.has_constant("sz"));
+        let stats = data.stats();
+        assert!(stats.iterations.is_empty());
+        assert!(stats.rules.iter().all(|rule| rule.derived == 0));
+        error
+    };
+
+    let error = run(Limits {
+        iterations: Some(10),
+        ..Limits::default()
+    });
+    assert_eq!(error.kind(), ErrorKind::Limit);
+    assert_eq!(
+        error.to_string(),
+        "error: the rules were applied 10 times without finding every fact"
+    );
+
+    let error = run(Limits {
+        facts: Some(100),
+        ..Limits::default()
+    });
+    assert_eq!(error.kind(), ErrorKind::Limit);
+
+    let start = Instant::now();
+    let error = run(Limits {
+        time: Some(Duration::from_millis(50)),
+        ..Limits::default()
+    });
+    assert_eq!(error.kind(), ErrorKind::Limit);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let cancel = Cancel::new();
+    cancel.cancel();
+    let error = run(Limits {
+        cancel: Some(cancel.clone()),
+        ..Limits::default()
+    });
+    assert_eq!(error.kind(), ErrorKind::Cancelled);
+
+    // Top-down queries give up too, with the error as their last item.
+    let query = Query::parse("num(X)", BlockList::OFF).unwrap();
+    for limits in [
+        Limits {
+            iterations: Some(10),
+            ..Limits::default()
+        },
+        Limits {
+            facts: Some(100),
+            ..Limits::default()
+        },
+        Limits {
+            time: Some(Duration::from_millis(50)),
+            ..Limits::default()
+        },
+        Limits {
+            cancel: Some(cancel.clone()),
+            ..Limits::default()
+        },
+    ] {
+        let mut data = data();
+        let last = data.query_tabled_with_limits(&query, &limits).last();
+        assert!(last.unwrap().is_err());
+    }
+
+    // A program which does finish isn't bothered by limits it stays under.
+    let mut data = star_wars_data();
+    let program = Program::parse(
+        " ancestor(X, Y) :- parent(X, Y). ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z). ",
+        BlockList::OFF,
+    )
+    .unwrap();
+    data.program(&program).unwrap();
+    cancel.reset();
+    data.run_with_limits(&Limits {
+        time: Some(Duration::from_secs(60)),
+        facts: Some(1000),
+        iterations: Some(100),
+        cancel: Some(cancel),
+    })
+    .unwrap();
+    assert!(data.contains("ancestor", &["vader", "leia"]));
+}
+
+#[test]
+fn stats() {
+    let mut data = star_wars_data();
+    let program = Program::parse(
+        " ancestor(X, Y) :- parent(X, Y). ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z). ",
+        BlockList::OFF,
+    )
+    .unwrap();
+    data.program(&program).unwrap();
+    let before = data.len();
+    data.run();
+
+    let stats = data.stats();
+    let recursive = stats
+        .rules
+        .iter()
+        .find(|rule| rule.rule == "ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).")
+        .expect("every rule has stats");
+    assert!(recursive.fired > 1);
+    assert!(recursive.considered >= recursive.derived);
+
+    let derived: usize = stats.rules.iter().map(|rule| rule.derived).sum();
+    let grown: usize = stats
+        .iterations
+        .iter()
+        .flat_map(|step| &step.growth)
+        .map(|(_, new)| new)
+        .sum();
+    assert_eq!(derived, data.len() - before);
+    assert_eq!(grown, data.len() - before);
+
+    let report = stats.to_string();
+    assert!(
+        report.contains("ancestor(X, Y) :- parent(X, Y)."),
+        "{report}"
+    );
+}
+
+#[test]
+fn trace() {
+    use datalog::Trace;
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let program = Program::parse(
+        " edge(a, b). edge(b, c). path(X, Y) :- edge(X, Y). \
+          path(X, Z) :- path(X, Y), edge(Y, Z). reached(Y) :- path(a, Y). ",
+        BlockList::OFF,
+    )
+    .unwrap();
+    let mut data = DataSet::default();
+    data.program(&program).unwrap();
+
+    let buffer = Buffer::default();
+    data.set_trace(Some(Trace::new(buffer.clone()).relations(["path"])));
+    data.run();
+
+    let trace = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(
+        trace,
+        "step 1\n\
+         \x20 path(a, b) by path(X, Y) :- edge(X, Y). with {X = a, Y = b}\n\
+         \x20 path(b, c) by path(X, Y) :- edge(X, Y). with {X = b, Y = c}\n\
+         step 2\n\
+         \x20 path(a, c) by path(X, Z) :- path(X, Y), edge(Y, Z). with {X = a, Z = c, Y = b}\n\
+         step 3\n\
+         step 4\n"
+    );
 }